@@ -2,6 +2,7 @@
 using System.Diagnostics;
 using System.IO;
 using System.Reflection;
+using System.Runtime.CompilerServices;
 using System.Runtime.InteropServices;
 using ABI_RC.Core;
 using HarmonyLib;
@@ -15,18 +16,17 @@ namespace FastDecrypt;
 public class Starter : MelonMod
 {
     static IntPtr NativeLibrary;
-    static DecryptDelegate Decrypt;
+    static MelonLogger.Instance Logger;
 
 #if DEBUG
     public static bool enabled = true;
-    static MelonLogger.Instance Logger;
 #endif
 
     public override void OnApplicationStart()
     {
-#if DEBUG
         Logger = LoggerInstance;
 
+#if DEBUG
         if (!enabled)
         {
             Logger.Msg("FastDecrypt is disabled");
@@ -62,10 +62,15 @@ public class Starter : MelonMod
             return;
         }
 
-        Decrypt = Marshal.GetDelegateForFunctionPointer<DecryptDelegate>(GetProcAddress(NativeLibrary, "decrypt"));
-        if (Decrypt == null)
+        // Loading the library registered Decrypt as an internal call, unless it was built
+        // without the boehm feature. Empty input is rejected before anything is allocated.
+        try
         {
-            LoggerInstance.Error("Native library load failed, mod won't work: failed to find decrypt function");
+            Decrypt(string.Empty, Array.Empty<byte>(), Array.Empty<byte>(), out _);
+        }
+        catch (Exception ex)
+        {
+            LoggerInstance.Error("Native library load failed, mod won't work: Decrypt isn't registered: {0}", ex.Message);
             return;
         }
 
@@ -85,13 +90,17 @@ public class Starter : MelonMod
             timer.Start();
 #endif
 
-            __result = new byte[bytes.Length + keyFrag.Length];
-            unsafe
+            __result = Decrypt(guid, bytes, keyFrag, out var status);
+
+            if (status is >= BadSignature and <= SizeMismatch)
             {
-                fixed (byte* b = bytes, k = keyFrag, d = __result)
-                {
-                    Decrypt(guid, (nuint)guid.Length, b, (nuint)bytes.Length, k, (nuint)keyFrag.Length, d);
-                }
+                Logger.Warning("{0}: Decrypted bundle is invalid (status {1}), truncated or corrupt download", guid, status);
+                return true;
+            }
+            if (status != 0)
+            {
+                Logger.Warning("{0}: Native decryption failed with status {1}, falling back", guid, status);
+                return true;
             }
 
 #if DEBUG
@@ -103,11 +112,12 @@ public class Starter : MelonMod
         }
     }
 
-    [UnmanagedFunctionPointer(CallingConvention.Cdecl, CharSet = CharSet.Ansi)]
-    public unsafe delegate void DecryptDelegate(string guid_ptr, nuint guid_len, byte* data_ptr, nuint data_len, byte* key_ptr, nuint key_len, byte* result_ptr);
+    // Statuses reported by the UnityFS header check, see DecryptError
+    const uint BadSignature = 11;
+    const uint SizeMismatch = 13;
 
-    [DllImport("kernel32", CharSet = CharSet.Ansi, ExactSpelling = true, SetLastError = true)]
-    static extern IntPtr GetProcAddress(IntPtr hModule, string procName);
+    [MethodImpl(MethodImplOptions.InternalCall)]
+    static extern byte[] Decrypt(string guid, byte[] bytes, byte[] keyFrag, out uint status);
 
     [DllImport("kernel32", CharSet = CharSet.Ansi, ExactSpelling = true, SetLastError = true)]
     static extern IntPtr LoadLibraryA(string libName);
@@ -1,6 +1,19 @@
 fn main() {
     let target = std::env::var("TARGET").unwrap();
 
+    // std brings its own runtime, without `runtime` whoever links the rlib provides one
+    if std::env::var_os("CARGO_FEATURE_STD").is_some()
+        || std::env::var_os("CARGO_FEATURE_RUNTIME").is_none()
+    {
+        return;
+    }
+
+    if std::env::var_os("CARGO_FEATURE_BOEHM").is_some() {
+        let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        println!("cargo:rustc-link-lib=mono-2.0-bdwgc");
+        println!("cargo:rustc-link-search={manifest_dir}/lib");
+    }
+
     match target.as_str() {
         "x86_64-pc-windows-msvc" => {
             println!("cargo:rustc-link-arg=/NODEFAULTLIB");
@@ -8,6 +21,10 @@ fn main() {
         "x86_64-pc-windows-gnu" => {
             println!("cargo:rustc-link-arg=-nostdlib");
         },
+        target if target.contains("-linux-") => {
+            // memcpy and friends, declared so the .so doesn't rely on the host having loaded libc
+            println!("cargo:rustc-link-arg=-lc");
+        },
         _ => {}
     }
 }
\ No newline at end of file
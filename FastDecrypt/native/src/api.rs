@@ -0,0 +1,196 @@
+//! Safe interface for Rust programs, the C exports at the crate root wrap the same functions.
+//!
+//! Depend on the crate with `default-features = false`, the default `runtime` feature brings
+//! the panic handler and entry points of the standalone library. [`decrypt`] needs `alloc`.
+
+use core::ops::Range;
+
+use crate::decrypt::{try_decrypt_progress, try_decrypt_range, try_decrypt_with, SegmentPlan};
+pub use crate::decrypt::{DecryptError as Error, PlannedSegment};
+pub use crate::scheme::Scheme;
+
+/// Decrypt a bundle into a new buffer.
+///
+/// `data` is the encrypted file and `key` the key fragment sent with the download, which may
+/// be empty.
+#[cfg(feature = "alloc")]
+pub fn decrypt(guid: &str, data: &[u8], key: &[u8]) -> Result<alloc::vec::Vec<u8>, Error> {
+    let total_size = data
+        .len()
+        .checked_add(key.len())
+        .ok_or(Error::LengthOverflow)?;
+    let mut dst = alloc::vec![0; total_size];
+    decrypt_into(guid, data, key, &mut dst)?;
+    Ok(dst)
+}
+
+/// Decrypt a bundle into `dst`, returns the number of bytes written.
+///
+/// `dst` has to hold at least `data.len() + key.len()` bytes, the rest is left alone.
+pub fn decrypt_into(guid: &str, data: &[u8], key: &[u8], dst: &mut [u8]) -> Result<usize, Error> {
+    try_decrypt_with(Scheme::V1, guid.as_bytes(), data, key, dst)?;
+    Ok(data.len() + key.len())
+}
+
+/// Decrypt only bytes `range` of a bundle into `dst`, which has to hold `range.len()` bytes.
+///
+/// Cheap for small ranges, like the header and blocks info at the start of a large bundle.
+pub fn decrypt_range(
+    guid: &str,
+    data: &[u8],
+    key: &[u8],
+    range: Range<usize>,
+    dst: &mut [u8],
+) -> Result<(), Error> {
+    try_decrypt_range(guid.as_bytes(), data, key, range, dst)
+}
+
+/// Same as [`decrypt_into`], but calls `progress` with the bytes done and the total every
+/// `interval` bytes. Returning false stops with [`Error::Aborted`], leaving the bytes done so
+/// far decrypted at the start of `dst`.
+pub fn decrypt_with_progress(
+    guid: &str,
+    data: &[u8],
+    key: &[u8],
+    dst: &mut [u8],
+    interval: usize,
+    progress: impl FnMut(usize, usize) -> bool,
+) -> Result<usize, Error> {
+    try_decrypt_progress(guid.as_bytes(), data, key, dst, interval, progress)?;
+    Ok(data.len() + key.len())
+}
+
+/// Encrypt a bundle for `new_guid` that was encrypted for `old_guid`, returns the new data and
+/// a key fragment of `new_key_len` bytes.
+#[cfg(feature = "alloc")]
+pub fn rekey(
+    old_guid: &str,
+    new_guid: &str,
+    data: &[u8],
+    key: &[u8],
+    new_key_len: usize,
+) -> Result<(alloc::vec::Vec<u8>, alloc::vec::Vec<u8>), Error> {
+    let total_size = data
+        .len()
+        .checked_add(key.len())
+        .ok_or(Error::LengthOverflow)?;
+    let new_data_len = total_size
+        .checked_sub(new_key_len)
+        .ok_or(Error::SizeMismatch)?;
+    let mut new_data = alloc::vec![0; new_data_len];
+    let mut new_key = alloc::vec![0; new_key_len];
+    crate::rekey::rekey(
+        old_guid.as_bytes(),
+        new_guid.as_bytes(),
+        data,
+        key,
+        &mut new_data,
+        &mut new_key,
+    )?;
+    Ok((new_data, new_key))
+}
+
+/// How a bundle of a given size is cut into segments and shuffled for a GUID.
+#[derive(Clone)]
+pub struct Plan(SegmentPlan);
+
+impl Plan {
+    pub fn new(guid: &str, total_size: usize) -> Result<Self, Error> {
+        Self::with_scheme(Scheme::V1, guid, total_size)
+    }
+
+    pub fn with_scheme(scheme: Scheme, guid: &str, total_size: usize) -> Result<Self, Error> {
+        SegmentPlan::with_scheme(scheme, guid.as_bytes(), total_size).map(Self)
+    }
+
+    /// Number of segments.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Size of the bundle the plan was made for.
+    pub fn total_size(&self) -> usize {
+        self.0.total_size()
+    }
+
+    /// Segments in the order they are stored in the encrypted data, `src` counts into the data
+    /// followed by the key fragment.
+    pub fn segments(&self) -> impl Iterator<Item = PlannedSegment> + '_ {
+        self.0.iter()
+    }
+
+    /// The parts of the segments needed for bytes `range` of the decrypted bundle, in the order
+    /// they are stored in the encrypted data.
+    pub fn window(&self, range: Range<usize>) -> impl Iterator<Item = PlannedSegment> + '_ {
+        self.0.window(range)
+    }
+}
+
+#[test]
+fn test_api() {
+    use crate::decrypt::encrypted_sample;
+
+    let guid = "0e9b2f6c-5a51-4bd4-a7e8-3c6f1b0d3a11";
+    let (plain, data, key) = encrypted_sample(guid.as_bytes(), 200_000, 300);
+
+    let mut dst = vec![0xaa; plain.len() + 10];
+    assert_eq!(decrypt_into(guid, &data, &key, &mut dst), Ok(plain.len()));
+    assert_eq!(dst[..plain.len()], plain[..]);
+    assert_eq!(dst[plain.len()..], [0xaa; 10]);
+    assert_eq!(
+        decrypt_into(guid, &data, &key, &mut dst[..plain.len() - 1]),
+        Err(Error::DstTooSmall)
+    );
+    assert_eq!(
+        decrypt_into("", &data, &key, &mut dst),
+        Err(Error::ZeroLength)
+    );
+    let mut calls = 0;
+    assert_eq!(
+        decrypt_with_progress(guid, &data, &key, &mut dst, 50_000, |_, _| {
+            calls += 1;
+            true
+        }),
+        Ok(plain.len())
+    );
+    assert_eq!(calls, 4);
+    #[cfg(feature = "alloc")]
+    {
+        assert_eq!(decrypt(guid, &data, &key).as_deref(), Ok(&plain[..]));
+        let other = "58c0d3e1-2f6a-4b97-8e14-c9a7b05d62f3";
+        let (new_data, new_key) = rekey(guid, other, &data, &key, 1000).unwrap();
+        assert_eq!(new_key.len(), 1000);
+        assert_eq!(
+            decrypt(other, &new_data, &new_key).as_deref(),
+            Ok(&plain[..])
+        );
+    }
+
+    let mut head = [0; 64];
+    assert_eq!(
+        decrypt_range(guid, &data, &key, 1000..1064, &mut head),
+        Ok(())
+    );
+    assert_eq!(head[..], plain[1000..1064]);
+
+    let plan = Plan::new(guid, plain.len()).unwrap();
+    assert_eq!(
+        plan.window(1000..1064)
+            .map(|segment| segment.len)
+            .sum::<usize>(),
+        64
+    );
+    assert_eq!(plan.total_size(), plain.len());
+    assert_eq!(plan.segments().count(), plan.len());
+    for segment in plan.segments() {
+        let src = [&data[..], &key[..]].concat();
+        assert_eq!(
+            src[segment.src..][..segment.len],
+            plain[segment.dst..][..segment.len]
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::decrypt::{try_decrypt, DecryptError};
+use crate::parallel::thread_count;
+
+/// A bundle to decrypt as part of a batch.
+pub struct Item<'a> {
+    pub guid: &'a [u8],
+    pub bytes: &'a [u8],
+    pub key_frag: &'a [u8],
+    pub dst: &'a mut [u8],
+}
+
+/// What happened to an [`Item`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Outcome {
+    pub result: Result<(), DecryptError>,
+    pub elapsed: Duration,
+}
+
+/// Decrypt every item with [`try_decrypt`], returns the outcomes in the order of the items.
+///
+/// The items are handed out to `threads` threads one at a time, so a large bundle doesn't hold
+/// up the small ones. `threads` of 0 uses the available parallelism, each item is decrypted on
+/// a single thread.
+pub fn decrypt_batch(items: &mut [Item], threads: usize) -> Vec<Outcome> {
+    let threads = thread_count(threads).clamp(1, items.len().max(1));
+
+    let queue = Mutex::new(items.iter_mut().enumerate());
+    let work = || {
+        let mut done = Vec::new();
+        loop {
+            // Poisoned means another thread panicked, the scope rethrows that
+            let Some((index, item)) = queue.lock().ok().and_then(|mut queue| queue.next()) else {
+                return done;
+            };
+            let start = Instant::now();
+            let result = try_decrypt(item.guid, item.bytes, item.key_frag, item.dst);
+            let elapsed = start.elapsed();
+            done.push((index, Outcome { result, elapsed }));
+        }
+    };
+
+    let mut outcomes = std::thread::scope(|scope| {
+        let helpers: Vec<_> = (1..threads).map(|_| scope.spawn(work)).collect();
+        let mut outcomes = work();
+        for helper in helpers {
+            outcomes.extend(helper.join().unwrap());
+        }
+        outcomes
+    });
+    outcomes.sort_unstable_by_key(|&(index, _)| index);
+    outcomes.into_iter().map(|(_, outcome)| outcome).collect()
+}
+
+/// [`Item`] as passed over the C ABI.
+///
+/// `nanos` is written by the batch, the time it took to decrypt this item.
+#[repr(C)]
+pub struct BatchItem {
+    pub guid_ptr: *const u8,
+    pub guid_len: usize,
+    pub data_ptr: *const u8,
+    pub data_len: usize,
+    pub key_ptr: *const u8,
+    pub key_len: usize,
+    pub dst_ptr: *mut u8,
+    pub dst_len: usize,
+    pub nanos: u64,
+}
+
+impl BatchItem {
+    /// # Safety
+    ///
+    /// Pointers are checked for null, the rest is up to the caller. A null `key_ptr` is
+    /// accepted if `key_len` is 0.
+    pub unsafe fn item(&self) -> Result<Item<'_>, DecryptError> {
+        if self.guid_ptr.is_null() || self.data_ptr.is_null() || self.dst_ptr.is_null() {
+            return Err(DecryptError::NullPointer);
+        }
+        Ok(Item {
+            guid: std::slice::from_raw_parts(self.guid_ptr, self.guid_len),
+            bytes: std::slice::from_raw_parts(self.data_ptr, self.data_len),
+            key_frag: crate::key_slice(self.key_ptr, self.key_len)?,
+            dst: std::slice::from_raw_parts_mut(self.dst_ptr, self.dst_len),
+        })
+    }
+}
+
+#[test]
+fn test_decrypt_batch() {
+    use crate::decrypt::encrypted_sample;
+
+    let guids = [
+        "8611ee9e-0c57-48d2-af32-7f980b0895db",
+        "32ceb35d-24fa-469f-8aa4-23851ac68f84",
+        "67e08c5c-d918-478e-ad8d-58e884fa53b4",
+        "2c99f767-53b9-463c-aa99-791b04cd9003",
+    ];
+    let (plains, encrypted): (Vec<_>, Vec<_>) = guids
+        .iter()
+        .zip([2498515, 5000, 690036, 123457])
+        .map(|(guid, size)| {
+            let (plain, bytes, key) = encrypted_sample(guid.as_bytes(), size, 100);
+            (plain, (bytes, key))
+        })
+        .unzip();
+
+    for threads in [0, 1, 3, 16] {
+        let mut dsts: Vec<_> = plains.iter().map(|plain| vec![0; plain.len()]).collect();
+        // The third one is missing a byte of its destination
+        dsts[2].pop();
+        let mut items: Vec<_> = guids
+            .iter()
+            .zip(&encrypted)
+            .zip(&mut dsts)
+            .map(|((guid, (bytes, key)), dst)| Item {
+                guid: guid.as_bytes(),
+                bytes,
+                key_frag: key,
+                dst,
+            })
+            .collect();
+
+        let results: Vec<_> = decrypt_batch(&mut items, threads)
+            .into_iter()
+            .map(|outcome| outcome.result)
+            .collect();
+        assert_eq!(
+            results,
+            [Ok(()), Ok(()), Err(DecryptError::DstTooSmall), Ok(())]
+        );
+        for i in [0, 1, 3] {
+            assert!(dsts[i] == plains[i]);
+        }
+    }
+
+    assert!(decrypt_batch(&mut [], 0).is_empty());
+}
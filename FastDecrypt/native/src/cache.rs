@@ -0,0 +1,224 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime};
+
+use xxhash_rust::xxh3::{xxh3_128, Xxh3};
+
+/// Entries start with this, followed by the XXH3-128 of the contents.
+const MAGIC: &[u8; 8] = b"CVRDEC\x00\x01";
+const HEADER_LEN: usize = MAGIC.len() + 16;
+const EXTENSION: &str = "dec";
+const TEMP_EXTENSION: &str = "tmp";
+/// Temporary files this old were left behind by a crashed writer.
+const STALE_TEMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Identifies a decrypted bundle by its GUID and a hash of the encrypted data and key fragment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey(u128);
+
+impl CacheKey {
+    pub fn new(guid: &[u8], bytes: &[u8], key_frag: &[u8]) -> Self {
+        let mut hasher = Xxh3::new();
+        // Length prefixes, so moving bytes between the parts changes the key
+        for part in [guid, bytes, key_frag] {
+            hasher.update(&(part.len() as u64).to_le_bytes());
+            hasher.update(part);
+        }
+        Self(hasher.digest128())
+    }
+
+    fn file_name(&self) -> String {
+        format!("{:032x}.{EXTENSION}", self.0)
+    }
+}
+
+/// Decrypted bundles in a directory, the least recently used ones are evicted once the entries
+/// take more than `max_size` bytes.
+///
+/// Entries are written to a temporary file and renamed into place, so readers never see a
+/// partial entry. Every read checks the contents against the stored hash, corrupt entries are
+/// deleted and reported as a miss.
+pub struct Cache {
+    dir: PathBuf,
+    max_size: u64,
+}
+
+impl Cache {
+    pub fn open(dir: impl Into<PathBuf>, max_size: u64) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir, max_size })
+    }
+
+    /// Cache used by the C exports, unset until `cache_open` is called.
+    pub fn global() -> &'static RwLock<Option<Cache>> {
+        static CACHE: RwLock<Option<Cache>> = RwLock::new(None);
+        &CACHE
+    }
+
+    /// Fill `dst` with the cached bundle, returns `false` if there is no valid entry of
+    /// exactly that size. An entry of another size is left alone.
+    pub fn get(&self, key: &CacheKey, dst: &mut [u8]) -> io::Result<bool> {
+        let path = self.dir.join(key.file_name());
+        // Closed again before it may get removed
+        let valid = {
+            let mut file = match File::open(&path) {
+                Ok(file) => file,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+                Err(err) => return Err(err),
+            };
+
+            if file.metadata()?.len() != (HEADER_LEN + dst.len()) as u64 {
+                return Ok(false);
+            }
+
+            let mut header = [0; HEADER_LEN];
+            file.read_exact(&mut header).is_ok()
+                && file.read_exact(dst).is_ok()
+                && header[..MAGIC.len()] == MAGIC[..]
+                && header[MAGIC.len()..] == xxh3_128(dst).to_le_bytes()
+        };
+        if !valid {
+            // Another reader may have removed it already
+            let _ = fs::remove_file(&path);
+            return Ok(false);
+        }
+
+        // The modification time is the eviction order, failing to update it only makes the
+        // entry look older
+        let _ = File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        Ok(true)
+    }
+
+    /// Store a decrypted bundle and evict old entries if the cache got too big.
+    pub fn put(&self, key: &CacheKey, data: &[u8]) -> io::Result<()> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let path = self.dir.join(key.file_name());
+        let temp = self.dir.join(format!(
+            "{}.{}-{}.{TEMP_EXTENSION}",
+            key.file_name(),
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let write = || {
+            {
+                let mut file = File::create(&temp)?;
+                file.write_all(MAGIC)?;
+                file.write_all(&xxh3_128(data).to_le_bytes())?;
+                file.write_all(data)?;
+                file.sync_all()?;
+            }
+            fs::rename(&temp, &path)
+        };
+        if let Err(err) = write() {
+            let _ = fs::remove_file(&temp);
+            return Err(err);
+        }
+
+        self.evict()
+    }
+
+    /// Delete the least recently used entries until the rest fits into `max_size` bytes.
+    pub fn evict(&self) -> io::Result<()> {
+        let now = SystemTime::now();
+        let mut entries = Vec::new();
+        let mut total = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            // Entries may disappear while we look at them
+            let Ok(entry) = entry else { continue };
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            let Ok(modified) = metadata.modified() else {
+                continue;
+            };
+            let path = entry.path();
+            match path.extension().and_then(|extension| extension.to_str()) {
+                Some(EXTENSION) => {
+                    total += metadata.len();
+                    entries.push((modified, metadata.len(), path));
+                }
+                Some(TEMP_EXTENSION)
+                    if now.duration_since(modified).unwrap_or_default() > STALE_TEMP_AGE =>
+                {
+                    let _ = fs::remove_file(&path);
+                }
+                _ => {}
+            }
+        }
+
+        entries.sort_unstable();
+        for (_, len, path) in entries {
+            if total <= self.max_size {
+                break;
+            }
+            // Open files can't be deleted on Windows, they get another chance next time
+            if fs::remove_file(&path).is_ok() {
+                total -= len;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_cache() {
+    let dir = std::env::temp_dir().join(format!("cvrdec-cache-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let cache = Cache::open(&dir, 3 * (HEADER_LEN as u64 + 1000)).unwrap();
+
+    let guid = b"17c267db-18c4-4900-bb73-ad323f082640";
+    let key = CacheKey::new(guid, &[1; 100], &[2; 10]);
+    assert_ne!(key, CacheKey::new(guid, &[1; 101], &[2; 9]));
+    assert_ne!(key, CacheKey::new(b"other", &[1; 100], &[2; 10]));
+
+    let data: Vec<u8> = (0..1000u32).map(|i| (i * 13) as u8).collect();
+    let mut dst = vec![0; 1000];
+    assert!(!cache.get(&key, &mut dst).unwrap());
+    cache.put(&key, &data).unwrap();
+    assert!(cache.get(&key, &mut dst).unwrap());
+    assert_eq!(dst, data);
+
+    // A lookup of the wrong size misses without dropping the entry
+    let path = dir.join(key.file_name());
+    assert!(!cache.get(&key, &mut [0; 999]).unwrap());
+    assert!(!cache.get(&key, &mut [0; 1001]).unwrap());
+    assert!(path.exists());
+    assert!(cache.get(&key, &mut dst).unwrap());
+
+    // Corrupt entries are dropped
+    let mut contents = fs::read(&path).unwrap();
+    contents[HEADER_LEN + 500] ^= 1;
+    fs::write(&path, contents).unwrap();
+    assert!(!cache.get(&key, &mut dst).unwrap());
+    assert!(!path.exists());
+
+    // Oldest first, reads count as use
+    let keys: Vec<_> = (0..4u8).map(|i| CacheKey::new(guid, &[i], &[])).collect();
+    for (i, key) in keys[..3].iter().enumerate() {
+        cache.put(key, &data).unwrap();
+        let age = Duration::from_secs(100 - i as u64 * 10);
+        File::options()
+            .write(true)
+            .open(dir.join(key.file_name()))
+            .unwrap()
+            .set_modified(SystemTime::now() - age)
+            .unwrap();
+    }
+    assert!(cache.get(&keys[0], &mut dst).unwrap());
+    cache.put(&keys[3], &data).unwrap();
+    let cached: Vec<_> = keys
+        .iter()
+        .map(|key| dir.join(key.file_name()).exists())
+        .collect();
+    assert_eq!(cached, [true, false, true, true]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,297 @@
+use crate::unityfs::{
+    BlockInfo, Bundle, Error, Header, BLOCK_INFO_PADDING, COMPRESSION_LZ4, COMPRESSION_LZ4HC,
+    COMPRESSION_LZMA, COMPRESSION_MASK, COMPRESSION_NONE,
+};
+
+/// Block flag for blocks that were written as a stream, the only one besides the compression.
+pub const BLOCK_STREAMED: u16 = 0x40;
+
+/// Decompress a single block of the given compression type into `dst`.
+///
+/// Unity's LZMA blocks carry the 5 byte properties header, but no size. That comes from the
+/// block info and is implied by `dst.len()`.
+pub fn decompress_block(compression: u32, src: &[u8], dst: &mut [u8]) -> Result<(), Error> {
+    match compression {
+        COMPRESSION_NONE if src.len() == dst.len() => dst.copy_from_slice(src),
+        COMPRESSION_NONE => return Err(Error::Corrupt),
+        COMPRESSION_LZ4 | COMPRESSION_LZ4HC => match crate::lz4::decompress(src, dst) {
+            Some(len) if len == dst.len() => {}
+            _ => return Err(Error::Corrupt),
+        },
+        COMPRESSION_LZMA => {
+            let options = lzma_rs::decompress::Options {
+                unpacked_size: lzma_rs::decompress::UnpackedSize::UseProvided(Some(
+                    dst.len() as u64
+                )),
+                ..Default::default()
+            };
+            let mut out = &mut dst[..];
+            lzma_rs::lzma_decompress_with_options(&mut &src[..], &mut out, &options)
+                .map_err(|_| Error::Corrupt)?;
+            if !out.is_empty() {
+                return Err(Error::Corrupt);
+            }
+        }
+        other => return Err(Error::UnsupportedCompression(other)),
+    }
+    Ok(())
+}
+
+/// Compression type of a block, rejecting flags we don't understand.
+pub(crate) fn block_compression(block: &BlockInfo) -> Result<u32, Error> {
+    if block.flags & !(COMPRESSION_MASK as u16 | BLOCK_STREAMED) != 0 {
+        return Err(Error::UnsupportedBlockFlags(block.flags));
+    }
+    Ok((block.flags & COMPRESSION_MASK as u16) as u32)
+}
+
+/// Uncompressed size of all data blocks, checked before anything of that size is allocated.
+///
+/// Fails with [`Error::Truncated`] if the compressed blocks reach past the end of `file`, so a
+/// made up blocks info can't ask for more than its blocks could hold.
+fn data_size(bundle: &Bundle, file: &[u8]) -> Result<usize, Error> {
+    let mut src = bundle.data_offset;
+    let mut size = 0usize;
+    for block in bundle.blocks() {
+        src = src
+            .checked_add(block.compressed_size as usize)
+            .filter(|&end| end <= file.len())
+            .ok_or(Error::Truncated)?;
+        size = size
+            .checked_add(block.uncompressed_size as usize)
+            .ok_or(Error::Corrupt)?;
+    }
+    Ok(size)
+}
+
+/// Decompress all data blocks into one buffer, node offsets are relative to its start.
+pub fn decompress_data(bundle: &Bundle, file: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut data = vec![0; data_size(bundle, file)?];
+    let mut src = bundle.data_offset;
+    let mut dst = 0;
+    for block in bundle.blocks() {
+        let compression = block_compression(&block)?;
+        // In bounds, see `data_size`
+        let input = &file[src..src + block.compressed_size as usize];
+        let output = &mut data[dst..dst + block.uncompressed_size as usize];
+        decompress_block(compression, input, output)?;
+        src += input.len();
+        dst += output.len();
+    }
+    Ok(data)
+}
+
+/// Rewrite `file` as a bundle without any compression.
+///
+/// The blocks are kept as they are, only their contents and flags change. The blocks info
+/// is stored right after the header.
+pub fn decompress_bundle(file: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut scratch = Vec::new();
+    let bundle = parse_bundle(file, &mut scratch)?;
+    let data = decompress_data(&bundle, file)?;
+
+    // Blocks info and directory
+    let mut info = bundle.uncompressed_data_hash().to_vec();
+    info.extend((bundle.blocks().count() as u32).to_be_bytes());
+    for block in bundle.blocks() {
+        info.extend(block.uncompressed_size.to_be_bytes());
+        info.extend(block.uncompressed_size.to_be_bytes());
+        info.extend((block.flags & BLOCK_STREAMED).to_be_bytes());
+    }
+    info.extend((bundle.nodes().count() as u32).to_be_bytes());
+    for node in bundle.nodes() {
+        info.extend(node.offset.to_be_bytes());
+        info.extend(node.size.to_be_bytes());
+        info.extend(node.flags.to_be_bytes());
+        info.extend(node.path);
+        info.push(0);
+    }
+
+    let header = &bundle.header;
+    let flags = header.flags & BLOCK_INFO_PADDING;
+    let mut out = b"UnityFS\0".to_vec();
+    out.extend(header.version.to_be_bytes());
+    out.extend(header.unity_version);
+    out.push(0);
+    out.extend(header.unity_revision);
+    out.push(0);
+    let size_offset = out.len();
+    out.extend(0u64.to_be_bytes());
+    out.extend((info.len() as u32).to_be_bytes());
+    out.extend((info.len() as u32).to_be_bytes());
+    out.extend(flags.to_be_bytes());
+    if header.version >= 7 {
+        out.resize(out.len().next_multiple_of(16), 0);
+    }
+    out.extend(&info);
+    if flags & BLOCK_INFO_PADDING != 0 {
+        out.resize(out.len().next_multiple_of(16), 0);
+    }
+    out.extend(&data);
+
+    let size = out.len() as u64;
+    out[size_offset..size_offset + 8].copy_from_slice(&size.to_be_bytes());
+    Ok(out)
+}
+
+/// Uncompressed contents of the node at `path`, only the blocks it spans are decompressed.
+pub fn extract_node(file: &[u8], path: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    let mut scratch = Vec::new();
+    let bundle = parse_bundle(file, &mut scratch)?;
+    let Some(node) = bundle.nodes().find(|node| node.path == path) else {
+        return Ok(None);
+    };
+    // The node has to lie within the data before its size is trusted
+    let end = node.offset.checked_add(node.size).ok_or(Error::Corrupt)?;
+    if end > data_size(&bundle, file)? as u64 {
+        return Err(Error::Corrupt);
+    }
+    let start = node.offset;
+
+    let mut out = vec![0; node.size as usize];
+    let mut block_buf = Vec::new();
+    let mut src = bundle.data_offset;
+    let mut block_start = 0;
+    let mut complete = node.size == 0;
+    for block in bundle.blocks() {
+        let block_end = block_start + block.uncompressed_size as u64;
+        let input_len = block.compressed_size as usize;
+        if block_end > start && block_start < end {
+            let input = &file[src..src + input_len];
+            block_buf.resize(block.uncompressed_size as usize, 0);
+            decompress_block(block_compression(&block)?, input, &mut block_buf)?;
+
+            let from = start.max(block_start);
+            let to = end.min(block_end);
+            out[(from - start) as usize..(to - start) as usize].copy_from_slice(
+                &block_buf[(from - block_start) as usize..(to - block_start) as usize],
+            );
+        }
+        if block_end >= end {
+            complete = true;
+            break;
+        }
+        src += input_len;
+        block_start = block_end;
+    }
+    if !complete {
+        return Err(Error::Truncated);
+    }
+    Ok(Some(out))
+}
+
+/// [`Bundle::parse`] with LZMA support for the blocks info.
+pub fn parse_bundle<'a>(file: &'a [u8], scratch: &'a mut Vec<u8>) -> Result<Bundle<'a>, Error> {
+    let (header, _) = Header::parse(file).ok_or(Error::BadSignature)?;
+    scratch.clear();
+    scratch.resize(header.uncompressed_blocks_info_size as usize, 0);
+    Bundle::parse_with(file, scratch, decompress_block)
+}
+
+#[test]
+fn test_decompress_bundle() {
+    use crate::unityfs::{sample_bundle_file, Node};
+
+    let plain: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+    let lzma = |data: &[u8]| {
+        let options = lzma_rs::compress::Options {
+            unpacked_size: lzma_rs::compress::UnpackedSize::SkipWritingToHeader,
+        };
+        let mut out = Vec::new();
+        lzma_rs::lzma_compress_with_options(&mut &data[..], &mut out, &options).unwrap();
+        out
+    };
+
+    // One block per compression type
+    let chunks = [
+        &plain[..1000],
+        &plain[1000..1800],
+        &plain[1800..2500],
+        &plain[2500..],
+    ];
+    let stored = [
+        lzma(chunks[0]),
+        crate::lz4::compress_literals(chunks[1]),
+        chunks[2].to_vec(),
+        crate::lz4::compress_literals(chunks[3]),
+    ];
+    let flags = [
+        COMPRESSION_LZMA as u16,
+        COMPRESSION_LZ4HC as u16 | BLOCK_STREAMED,
+        COMPRESSION_NONE as u16,
+        COMPRESSION_LZ4 as u16,
+    ];
+    let blocks: Vec<_> = (0..4)
+        .map(|i| BlockInfo {
+            uncompressed_size: chunks[i].len() as u32,
+            compressed_size: stored[i].len() as u32,
+            flags: flags[i],
+        })
+        .collect();
+    let nodes = [
+        Node {
+            offset: 0,
+            size: 1200,
+            flags: 4,
+            path: b"CAB-a",
+        },
+        Node {
+            offset: 1200,
+            size: 1800,
+            flags: 0,
+            path: b"CAB-a.resS",
+        },
+    ];
+    let file = sample_bundle_file(&blocks, &nodes, &stored.concat(), COMPRESSION_LZ4HC, true);
+
+    let out = decompress_bundle(&file).unwrap();
+    assert!(crate::unityfs::validate(&out).is_ok());
+    let mut scratch = Vec::new();
+    let bundle = parse_bundle(&out, &mut scratch).unwrap();
+    assert_eq!(bundle.header.flags & COMPRESSION_MASK, COMPRESSION_NONE);
+    assert!(bundle
+        .blocks()
+        .all(|block| block.flags & COMPRESSION_MASK as u16 == 0));
+    assert!(bundle.nodes().eq(nodes));
+    assert_eq!(decompress_data(&bundle, &out).unwrap(), plain);
+
+    assert_eq!(
+        extract_node(&file, b"CAB-a").unwrap().unwrap(),
+        &plain[..1200]
+    );
+    assert_eq!(
+        extract_node(&file, b"CAB-a.resS").unwrap().unwrap(),
+        &plain[1200..]
+    );
+    assert_eq!(extract_node(&file, b"CAB-b").unwrap(), None);
+
+    // Nodes past the end of the data, or wrapping around
+    for (offset, size) in [(1200, 1801), (u64::MAX, 2)] {
+        let nodes = [Node {
+            offset,
+            size,
+            flags: 0,
+            path: b"CAB-a",
+        }];
+        let file = sample_bundle_file(&blocks, &nodes, &stored.concat(), COMPRESSION_NONE, false);
+        assert_eq!(extract_node(&file, b"CAB-a"), Err(Error::Corrupt));
+    }
+
+    // Blocks claiming more than the file holds
+    let mut huge = blocks.clone();
+    huge[3].compressed_size = u32::MAX;
+    huge[3].uncompressed_size = u32::MAX;
+    let file = sample_bundle_file(&huge, &nodes, &stored.concat(), COMPRESSION_NONE, false);
+    let mut scratch = Vec::new();
+    let bundle = parse_bundle(&file, &mut scratch).unwrap();
+    assert_eq!(decompress_data(&bundle, &file), Err(Error::Truncated));
+
+    // Unknown block flags
+    let mut blocks = blocks;
+    blocks[2].flags |= 0x100;
+    let file = sample_bundle_file(&blocks, &nodes, &stored.concat(), COMPRESSION_NONE, false);
+    assert_eq!(
+        decompress_bundle(&file).err(),
+        Some(Error::UnsupportedBlockFlags(0x100))
+    );
+}
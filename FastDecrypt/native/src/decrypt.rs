@@ -1,12 +1,31 @@
+use core::ops::Range;
+
 use crc::Crc;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::scheme::Scheme;
 
 const X32: Crc<u32> = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
 
-fn compute_crc(data: &[u8]) -> u32 {
+pub fn compute_crc(data: &[u8]) -> u32 {
     X32.checksum(data)
 }
 
-struct CVRRand {
+/// [`compute_crc`] of a UTF-16 GUID narrowed to ASCII, `None` if it has any other characters.
+pub fn compute_crc_utf16(guid: &[u16]) -> Option<u32> {
+    let mut digest = X32.digest();
+    let mut narrow = [0; 64];
+    for chunk in guid.chunks(narrow.len()) {
+        for (out, &c) in narrow.iter_mut().zip(chunk) {
+            *out = u8::try_from(c).ok().filter(u8::is_ascii)?;
+        }
+        digest.update(&narrow[..chunk.len()]);
+    }
+    Some(digest.finalize())
+}
+
+/// The PRNG the client derives segment lengths and the scramble order from.
+pub struct CVRRand {
     state: i64,
     crc: i64,
     frag_size: i64,
@@ -14,14 +33,26 @@ struct CVRRand {
 
 impl CVRRand {
     pub fn new(crc: u32, size: usize) -> Self {
+        Self::with_scheme(Scheme::V1, crc, size)
+    }
+
+    pub fn with_scheme(scheme: Scheme, crc: u32, size: usize) -> Self {
+        let params = scheme.params();
         Self {
-            state: 0x3fffffefffffff_i64,
+            state: params.seed,
             crc: crc.into(),
-            frag_size: u32::max((size / 100) as u32, 1000).into(),
+            frag_size: u32::max((size / params.divisor) as u32, params.min_segment).into(),
         }
     }
+
+    /// Next value, between 1 and twice the fragment size.
+    ///
+    /// Returned as u64 rather than usize, twice the fragment size can exceed 32 bits and
+    /// the scramble takes the value modulo the segment count, so narrowing first would change
+    /// the plan on 32-bit targets.
     #[inline(always)]
-    fn next(&mut self) -> usize {
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> u64 {
         unsafe {
             self.state = self
                 .state
@@ -30,57 +61,441 @@ impl CVRRand {
                 .checked_rem(self.frag_size)
                 .unwrap_unchecked()
                 .wrapping_add(self.frag_size);
-            core::mem::transmute(self.state)
+            // The remainder is above -frag_size, so the state is positive
+            self.state as u64
         }
     }
 }
 
-/// Steps:
-/// - Seed PRNG with CRC32 of the GUID and clamp it between 1/100 and 2/100 of the data size
-/// - Segment the data in, at most, 100 chunks of random length
-/// - Scramble segments, skipping the first one (UnityFS header)
-/// - Reassemble the data with these segments
-pub fn decrypt_internal(guid: &[u8], bytes: &[u8], key_frag: &[u8], dst: &mut [u8]) {
+#[derive(Clone, Copy)]
+pub(crate) struct Segment {
+    pub offset: usize,
+    pub end: usize,
+}
+
+/// Reasons the checked entry point refuses to decrypt.
+///
+/// The discriminants are returned as status codes over the C ABI, 0 means success.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecryptError {
+    /// A pointer passed over the C ABI was null
+    NullPointer = 1,
+    /// GUID or data is empty
+    ZeroLength = 2,
+    /// Destination can't hold `bytes.len() + key_frag.len()`
+    DstTooSmall = 3,
+    /// The PRNG split the data into more than 100 segments
+    SegmentOverflow = 4,
+    /// `bytes.len() + key_frag.len()` doesn't fit into usize
+    LengthOverflow = 5,
+    /// Reading the input or writing the output of a stream failed
+    #[cfg(feature = "std")]
+    Io = 6,
+    /// Too many jobs are waiting for the background worker
+    #[cfg(feature = "std")]
+    QueueFull = 7,
+    /// The job id was never handed out or its result was already collected
+    #[cfg(feature = "std")]
+    UnknownJob = 8,
+    /// The job was cancelled before it started
+    #[cfg(feature = "std")]
+    Cancelled = 9,
+    /// The job is still queued or running
+    #[cfg(feature = "std")]
+    Pending = 10,
+    /// The output doesn't start with a UnityFS header
+    BadSignature = 11,
+    /// The UnityFS format version isn't one we know
+    UnsupportedVersion = 12,
+    /// The size in the UnityFS header doesn't match `bytes.len() + key_frag.len()`
+    SizeMismatch = 13,
+    /// The cache has no valid entry for the GUID and input
+    #[cfg(feature = "std")]
+    CacheMiss = 14,
+    /// No cache directory was configured
+    #[cfg(feature = "std")]
+    NoCache = 15,
+    /// The hash of the output differs from the expected one
+    HashMismatch = 16,
+    /// The scheme id isn't one we know
+    UnknownScheme = 17,
+    /// The requested range isn't within the decrypted bundle
+    RangeOutOfBounds = 18,
+    /// The GUID has characters outside of ASCII
+    NonAsciiGuid = 19,
+    /// The progress callback asked to stop
+    Aborted = 20,
+    /// The key fragment was fed before the body
+    MissingBody = 21,
+}
+
+impl core::fmt::Display for DecryptError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Self::NullPointer => "null pointer",
+            Self::ZeroLength => "empty GUID or data",
+            Self::DstTooSmall => "destination too small",
+            Self::SegmentOverflow => "too many segments",
+            Self::LengthOverflow => "length overflow",
+            #[cfg(feature = "std")]
+            Self::Io => "I/O error",
+            #[cfg(feature = "std")]
+            Self::QueueFull => "queue full",
+            #[cfg(feature = "std")]
+            Self::UnknownJob => "unknown job",
+            #[cfg(feature = "std")]
+            Self::Cancelled => "cancelled",
+            #[cfg(feature = "std")]
+            Self::Pending => "still pending",
+            Self::BadSignature => "not a UnityFS bundle",
+            Self::UnsupportedVersion => "unsupported UnityFS version",
+            Self::SizeMismatch => "size doesn't match the UnityFS header",
+            #[cfg(feature = "std")]
+            Self::CacheMiss => "not cached",
+            #[cfg(feature = "std")]
+            Self::NoCache => "no cache configured",
+            Self::HashMismatch => "hash mismatch",
+            Self::UnknownScheme => "unknown scheme",
+            Self::RangeOutOfBounds => "range out of bounds",
+            Self::NonAsciiGuid => "GUID isn't ASCII",
+            Self::Aborted => "aborted",
+            Self::MissingBody => "body not decrypted yet",
+        })
+    }
+}
+
+impl core::error::Error for DecryptError {}
+
+/// A segment as seen from the outside: `len` bytes at `src` in the encrypted data (data
+/// followed by key fragment) belong at `dst` in the decrypted bundle.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlannedSegment {
+    pub src: usize,
+    pub dst: usize,
+    pub len: usize,
+}
+
+/// How a bundle of a given size is cut into segments and shuffled for a GUID.
+#[derive(Clone)]
+pub struct SegmentPlan {
+    segments: [Segment; 100],
+    len: usize,
+}
+
+impl SegmentPlan {
+    /// Plan of the current scheme, [`Scheme::V1`].
+    ///
+    /// Steps:
+    /// - Seed PRNG with CRC32 of the GUID and clamp it between 1/100 and 2/100 of the data size
+    /// - Segment the data in, at most, 100 chunks of random length
+    /// - Scramble segments, skipping the first one (UnityFS header)
+    pub fn new(guid: &[u8], total_size: usize) -> Result<Self, DecryptError> {
+        Self::with_scheme(Scheme::V1, guid, total_size)
+    }
+
+    pub fn with_scheme(
+        scheme: Scheme,
+        guid: &[u8],
+        total_size: usize,
+    ) -> Result<Self, DecryptError> {
+        Self::from_crc(scheme, compute_crc(guid), total_size)
+    }
+
+    /// Plan for a GUID whose CRC32 is already known, e.g. from [`compute_crc_utf16`].
+    pub fn from_crc(scheme: Scheme, crc: u32, total_size: usize) -> Result<Self, DecryptError> {
+        // Seed PRNG
+        let mut random = CVRRand::with_scheme(scheme, crc, total_size);
+
+        // Segment data
+        let mut segments = [Segment { offset: 0, end: 0 }; 100];
+        let mut i = 0;
+        let mut offset = 0;
+        while offset < total_size {
+            let len = usize::try_from(random.next()).unwrap_or(usize::MAX);
+            let end = offset.saturating_add(len).min(total_size);
+            *segments.get_mut(i).ok_or(DecryptError::SegmentOverflow)? = Segment { offset, end };
+            i += 1;
+            offset = end;
+        }
+
+        // Scramble
+        let length = i;
+        let fixed = scheme.params().fixed;
+        for i in fixed..length {
+            // At most 100 segments, so the remainder fits into usize
+            let index = unsafe {
+                (random
+                    .next()
+                    .checked_rem(length.wrapping_sub(fixed) as u64)
+                    .unwrap_unchecked() as usize)
+                    .wrapping_add(fixed)
+            };
+            unsafe {
+                let tmp = *segments.get_unchecked(index);
+                *segments.get_unchecked_mut(index) = *segments.get_unchecked(i);
+                *segments.get_unchecked_mut(i) = tmp
+            };
+        }
+
+        Ok(Self {
+            segments,
+            len: length,
+        })
+    }
+
+    /// Number of segments.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Size of the bundle the plan was made for.
+    pub fn total_size(&self) -> usize {
+        self.segments().iter().map(|s| s.end - s.offset).sum()
+    }
+
+    /// Segments in the order they are stored in the encrypted data.
+    pub fn iter(&self) -> impl Iterator<Item = PlannedSegment> + '_ {
+        self.segments().iter().scan(0, |src, segment| {
+            let len = segment.end - segment.offset;
+            let planned = PlannedSegment {
+                src: *src,
+                dst: segment.offset,
+                len,
+            };
+            *src += len;
+            Some(planned)
+        })
+    }
+
+    /// The parts of the segments that overlap `range` of the decrypted bundle, in the order they
+    /// are stored in the encrypted data. These are the only source bytes needed to produce the
+    /// range.
+    pub fn window(&self, range: Range<usize>) -> impl Iterator<Item = PlannedSegment> + '_ {
+        self.iter().filter_map(move |segment| {
+            let start = segment.dst.max(range.start);
+            let end = (segment.dst + segment.len).min(range.end);
+            (start < end).then(|| PlannedSegment {
+                src: segment.src + (start - segment.dst),
+                dst: start,
+                len: end - start,
+            })
+        })
+    }
+
+    /// Segments in the order they appear in the decrypted bundle, only the first [`Self::len`]
+    /// entries are used.
+    pub(crate) fn destination_order(&self) -> [PlannedSegment; 100] {
+        let mut segments = [PlannedSegment {
+            src: 0,
+            dst: 0,
+            len: 0,
+        }; 100];
+        for (slot, segment) in segments.iter_mut().zip(self.iter()) {
+            *slot = segment;
+        }
+        segments[..self.len].sort_unstable_by_key(|segment| segment.dst);
+        segments
+    }
+
+    /// Destination ranges in the order they are stored in the encrypted data.
+    pub(crate) fn segments(&self) -> &[Segment] {
+        unsafe { self.segments.get_unchecked(..self.len) }
+    }
+}
+
+/// Reassemble the data from the scrambled segments.
+///
+/// # Safety
+///
+/// Nothing is checked, see [`try_decrypt`] for that. `guid` must not be empty, `dst` must hold
+/// at least `bytes.len() + key_frag.len()` bytes and the plan for that size must fit in 100
+/// segments.
+pub unsafe fn decrypt_internal(guid: &[u8], bytes: &[u8], key_frag: &[u8], dst: &mut [u8]) {
     let total_size = bytes.len() + key_frag.len();
 
-    // Seed PRNG
-    let mut random = CVRRand::new(compute_crc(guid), total_size);
+    let plan = SegmentPlan::new(guid, total_size).unwrap_unchecked();
+    let segments = plan.segments();
+
+    reassemble(segments, bytes, key_frag, dst);
+}
+
+/// Same as [`decrypt_internal`], but validates the input instead of trusting it.
+///
+/// Only the first `bytes.len() + key_frag.len()` bytes of `dst` are written.
+pub fn try_decrypt(
+    guid: &[u8],
+    bytes: &[u8],
+    key_frag: &[u8],
+    dst: &mut [u8],
+) -> Result<(), DecryptError> {
+    try_decrypt_with(Scheme::V1, guid, bytes, key_frag, dst)
+}
 
-    // Segment data
-    #[derive(Clone, Copy)]
-    struct Segment {
-        offset: usize,
-        end: usize,
+/// Validate the input shared by the `try_decrypt` variants, returns the size of the bundle.
+pub(crate) fn check_input(
+    guid: &[u8],
+    bytes: &[u8],
+    key_frag: &[u8],
+) -> Result<usize, DecryptError> {
+    if guid.is_empty() || bytes.is_empty() {
+        return Err(DecryptError::ZeroLength);
     }
-    let mut segments: [Segment; 100] = [Segment { offset: 0, end: 0 }; 100];
-    let mut i = 0;
-    let mut offset = 0;
-    while offset < total_size {
-        let len = random.next();
-        let end = (offset + len).min(total_size);
-        unsafe { *segments.get_unchecked_mut(i) = Segment { offset, end } };
-        i += 1;
-        offset = end;
-    }
-    let segments = unsafe { segments.get_unchecked_mut(..i) };
-
-    // Scramble
-    let length = segments.len();
-    for i in 1..length {
-        let index = unsafe {
-            random
-                .next()
-                .checked_rem(length.wrapping_sub(1))
-                .unwrap_unchecked()
-                .wrapping_add(1)
-        };
-        unsafe {
-            let tmp = *segments.get_unchecked(index);
-            *segments.get_unchecked_mut(index) = *segments.get_unchecked(i);
-            *segments.get_unchecked_mut(i) = tmp
-        };
+    bytes
+        .len()
+        .checked_add(key_frag.len())
+        .ok_or(DecryptError::LengthOverflow)
+}
+
+/// [`try_decrypt`] for bundles encrypted with another scheme.
+pub fn try_decrypt_with(
+    scheme: Scheme,
+    guid: &[u8],
+    bytes: &[u8],
+    key_frag: &[u8],
+    dst: &mut [u8],
+) -> Result<(), DecryptError> {
+    let total_size = check_input(guid, bytes, key_frag)?;
+    let dst = dst.get_mut(..total_size).ok_or(DecryptError::DstTooSmall)?;
+
+    let plan = SegmentPlan::with_scheme(scheme, guid, total_size)?;
+
+    // The plan covers `total_size` and `dst` was cut to it
+    unsafe { reassemble(plan.segments(), bytes, key_frag, dst) };
+
+    Ok(())
+}
+
+/// Same as [`try_decrypt`], but also returns the XXH3-128 (XXH128) of the decrypted bundle.
+///
+/// The segments are copied in destination order and each one is hashed right after it was
+/// copied, while it's still in cache. If `expected` is given and differs,
+/// [`DecryptError::HashMismatch`] is returned, `dst` is written either way.
+pub fn try_decrypt_hashed(
+    guid: &[u8],
+    bytes: &[u8],
+    key_frag: &[u8],
+    dst: &mut [u8],
+    expected: Option<u128>,
+) -> Result<u128, DecryptError> {
+    let total_size = check_input(guid, bytes, key_frag)?;
+    let dst = dst.get_mut(..total_size).ok_or(DecryptError::DstTooSmall)?;
+
+    let plan = SegmentPlan::new(guid, total_size)?;
+
+    let mut hasher = Xxh3::new();
+    for segment in &plan.destination_order()[..plan.len()] {
+        let out = &mut dst[segment.dst..][..segment.len];
+        copy_from(bytes, key_frag, segment.src, out);
+        hasher.update(out);
+    }
+
+    let hash = hasher.digest128();
+    match expected {
+        Some(expected) if expected != hash => Err(DecryptError::HashMismatch),
+        _ => Ok(hash),
+    }
+}
+
+/// Same as [`try_decrypt`], but only produces bytes `range` of the decrypted bundle.
+///
+/// `dst` receives the range, starting at `dst[0]`. Only the segments overlapping the range are
+/// read, see [`SegmentPlan::window`] for the source bytes that are needed.
+pub fn try_decrypt_range(
+    guid: &[u8],
+    bytes: &[u8],
+    key_frag: &[u8],
+    range: Range<usize>,
+    dst: &mut [u8],
+) -> Result<(), DecryptError> {
+    let total_size = check_input(guid, bytes, key_frag)?;
+    if range.start > range.end || range.end > total_size {
+        return Err(DecryptError::RangeOutOfBounds);
+    }
+    let start = range.start;
+    let dst = dst
+        .get_mut(..range.len())
+        .ok_or(DecryptError::DstTooSmall)?;
+
+    let plan = SegmentPlan::new(guid, total_size)?;
+
+    for segment in plan.window(range) {
+        let out = &mut dst[segment.dst - start..][..segment.len];
+        copy_from(bytes, key_frag, segment.src, out);
+    }
+
+    Ok(())
+}
+
+/// Same as [`try_decrypt`], but reports progress to `progress` every `interval` bytes.
+///
+/// The segments are copied in destination order, `progress` receives the number of bytes done
+/// and the total whenever a multiple of `interval` is reached, and once more at the end. An
+/// `interval` of 0 only reports the end. If `progress` returns false, the decryption stops with
+/// [`DecryptError::Aborted`], the bytes done so far are decrypted at the start of `dst` and the
+/// rest is left alone.
+pub fn try_decrypt_progress(
+    guid: &[u8],
+    bytes: &[u8],
+    key_frag: &[u8],
+    dst: &mut [u8],
+    interval: usize,
+    mut progress: impl FnMut(usize, usize) -> bool,
+) -> Result<(), DecryptError> {
+    let total_size = check_input(guid, bytes, key_frag)?;
+    let dst = dst.get_mut(..total_size).ok_or(DecryptError::DstTooSmall)?;
+    let interval = match interval {
+        0 => total_size,
+        interval => interval,
+    };
+
+    let plan = SegmentPlan::new(guid, total_size)?;
+
+    let mut done = 0;
+    for segment in &plan.destination_order()[..plan.len()] {
+        let mut src = segment.src;
+        let end = segment.dst + segment.len;
+        while done < end {
+            // Stop at the next multiple of the interval
+            let step = (interval - done % interval).min(end - done);
+            copy_from(bytes, key_frag, src, &mut dst[done..][..step]);
+            src += step;
+            done += step;
+            if (done % interval == 0 || done == total_size) && !progress(done, total_size) {
+                return Err(DecryptError::Aborted);
+            }
+        }
     }
 
+    Ok(())
+}
+
+/// Fill `out` from `src` onwards in `bytes` followed by `key_frag`.
+pub(crate) fn copy_from(bytes: &[u8], key_frag: &[u8], src: usize, out: &mut [u8]) {
+    let split = bytes.len().saturating_sub(src).min(out.len());
+    let (head, tail) = out.split_at_mut(split);
+    head.copy_from_slice(&bytes[src.min(bytes.len())..][..split]);
+    let key_src = src.saturating_sub(bytes.len());
+    tail.copy_from_slice(&key_frag[key_src..][..tail.len()]);
+}
+
+/// Copy the segments into place.
+///
+/// # Safety
+///
+/// The segments have to cover `bytes` and `key_frag` exactly and `dst` has to be large enough.
+#[inline(always)]
+pub(crate) unsafe fn reassemble(
+    segments: &[Segment],
+    bytes: &[u8],
+    key_frag: &[u8],
+    dst: &mut [u8],
+) {
     // Reassemble
     let mut offset = 0;
     for segment in segments.iter() {
@@ -121,6 +536,167 @@ pub fn decrypt_internal(guid: &[u8], bytes: &[u8], key_frag: &[u8], dst: &mut [u
     }
 }
 
+/// Same as [`try_decrypt`], but `buf` holds `bytes` followed by `key_frag` and is decrypted
+/// without a second buffer.
+///
+/// The scrambled segments are sorted back into place with rotations, so this trades some
+/// speed for not having to keep two copies of the bundle around.
+pub fn decrypt_in_place(guid: &[u8], buf: &mut [u8]) -> Result<(), DecryptError> {
+    if guid.is_empty() || buf.is_empty() {
+        return Err(DecryptError::ZeroLength);
+    }
+
+    let mut plan = SegmentPlan::new(guid, buf.len())?;
+
+    sort_segments(buf, &mut plan.segments[..plan.len]);
+
+    Ok(())
+}
+
+/// Merge sort the segments stored back to back in `buf` by their destination offset.
+fn sort_segments(buf: &mut [u8], segments: &mut [Segment]) {
+    if segments.len() < 2 {
+        return;
+    }
+    let mid = segments.len() / 2;
+    let split = segments[..mid].iter().map(|s| s.end - s.offset).sum();
+    let (left, right) = buf.split_at_mut(split);
+    sort_segments(left, &mut segments[..mid]);
+    sort_segments(right, &mut segments[mid..]);
+    merge_segments(buf, segments, mid);
+}
+
+/// Merge the sorted runs `segments[..mid]` and `segments[mid..]` in place.
+///
+/// Picks a pivot from the longer run, rotates everything that has to pass it across and
+/// recurses on both sides of the pivot.
+fn merge_segments(buf: &mut [u8], segments: &mut [Segment], mid: usize) {
+    if mid == 0 || mid == segments.len() || segments[mid - 1].offset < segments[mid].offset {
+        return;
+    }
+
+    // Move [i..mid) behind [mid..j), leaving a pivot in its final position. Everything in
+    // front of it sorts before the pivot, everything behind it after.
+    let (i, j, pivot, tail_mid) = if mid >= segments.len() - mid {
+        let i = mid / 2;
+        let key = segments[i].offset;
+        let j = mid + segments[mid..].partition_point(|s| s.offset < key);
+        (i, j, i + (j - mid), mid - i - 1)
+    } else {
+        let j = mid + (segments.len() - mid) / 2;
+        let key = segments[j].offset;
+        let i = segments[..mid].partition_point(|s| s.offset < key);
+        (i, j + 1, i + (j - mid), mid - i)
+    };
+
+    let size = |segments: &[Segment]| segments.iter().map(|s| s.end - s.offset).sum::<usize>();
+    let start = size(&segments[..i]);
+    let end = start + size(&segments[i..j]);
+    buf[start..end].rotate_left(size(&segments[i..mid]));
+    segments[i..j].rotate_left(mid - i);
+
+    let (head_segments, tail_segments) = segments.split_at_mut(pivot);
+    let (head, tail) = buf.split_at_mut(size(head_segments));
+    let tail = &mut tail[size(&tail_segments[..1])..];
+    merge_segments(head, head_segments, i);
+    merge_segments(tail, &mut tail_segments[1..], tail_mid);
+}
+
+/// Inverse of [`decrypt_internal`].
+///
+/// Scatters the plain bundle in `src` into the scrambled layout and splits it
+/// into `bytes` and `key_frag`. The key fragment length is taken from
+/// `key_frag`, `bytes` has to hold the remaining `src.len() - key_frag.len()`.
+///
+/// # Safety
+///
+/// Nothing is checked, see [`try_encrypt`] for that. `guid` must not be empty, `key_frag` must
+/// not be longer than `src`, `bytes` must hold the rest and the plan for `src.len()` must fit in
+/// 100 segments.
+pub unsafe fn encrypt_internal(guid: &[u8], src: &[u8], bytes: &mut [u8], key_frag: &mut [u8]) {
+    let total_size = src.len();
+
+    let plan = SegmentPlan::new(guid, total_size).unwrap_unchecked();
+    let segments = plan.segments();
+
+    disassemble(segments, src, bytes, key_frag);
+}
+
+/// Same as [`encrypt_internal`], but validates the input instead of trusting it.
+///
+/// `bytes` and `key_frag` have to add up to `src.len()`, otherwise
+/// [`DecryptError::SizeMismatch`] is returned.
+pub fn try_encrypt(
+    guid: &[u8],
+    src: &[u8],
+    bytes: &mut [u8],
+    key_frag: &mut [u8],
+) -> Result<(), DecryptError> {
+    // Without data the result couldn't be decrypted again
+    let total_size = check_input(guid, bytes, key_frag)?;
+    if total_size != src.len() {
+        return Err(DecryptError::SizeMismatch);
+    }
+
+    let plan = SegmentPlan::new(guid, total_size)?;
+
+    // The plan covers `src` and `bytes` and `key_frag` add up to it
+    unsafe { disassemble(plan.segments(), src, bytes, key_frag) };
+
+    Ok(())
+}
+
+/// Copy the segments out of place, the inverse of [`reassemble`].
+///
+/// # Safety
+///
+/// The segments have to cover `src` exactly and `bytes` and `key_frag` have to add up to it.
+#[inline(always)]
+unsafe fn disassemble(segments: &[Segment], src: &[u8], bytes: &mut [u8], key_frag: &mut [u8]) {
+    let split = bytes.len();
+
+    // Disassemble
+    let mut offset = 0;
+    for segment in segments.iter() {
+        let length = segment.end - segment.offset;
+        match (offset > split, offset + length > split) {
+            (false, false) => unsafe {
+                core::ptr::copy_nonoverlapping(
+                    src.get_unchecked(segment.offset..segment.end).as_ptr(),
+                    bytes
+                        .get_unchecked_mut(offset..offset + length)
+                        .as_mut_ptr(),
+                    length,
+                );
+            },
+            (false, true) => {
+                let remainder = split - offset;
+                let temp = segment.offset + remainder;
+                unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        src.get_unchecked(segment.offset..temp).as_ptr(),
+                        bytes.get_unchecked_mut(offset..).as_mut_ptr(),
+                        remainder,
+                    );
+                    core::ptr::copy_nonoverlapping(
+                        src.get_unchecked(temp..segment.end).as_ptr(),
+                        key_frag
+                            .get_unchecked_mut(..length - remainder)
+                            .as_mut_ptr(),
+                        length - remainder,
+                    );
+                }
+            }
+            (true, _) => unsafe {
+                let src = src.get_unchecked(segment.offset..segment.end);
+                let dst = key_frag.get_unchecked_mut(offset - split..offset + length - split);
+                core::ptr::copy_nonoverlapping(src.as_ptr(), dst.as_mut_ptr(), length);
+            },
+        }
+        offset += length;
+    }
+}
+
 #[test]
 fn test_compute_crc() {
     assert_eq!(
@@ -159,11 +735,23 @@ fn test_compute_crc() {
         compute_crc(b"9d1d8585-9c0b-40d9-8721-76f21cc745f2"),
         2255572015
     );
+
+    let utf16 = |guid: &str| guid.encode_utf16().collect::<Vec<_>>();
+    assert_eq!(
+        compute_crc_utf16(&utf16("9d1d8585-9c0b-40d9-8721-76f21cc745f2")),
+        Some(2255572015)
+    );
+    let long = "0123456789abcdef".repeat(10);
+    assert_eq!(
+        compute_crc_utf16(&utf16(&long)),
+        Some(compute_crc(long.as_bytes()))
+    );
+    assert_eq!(compute_crc_utf16(&utf16("9d1d8585-9c0b-40d9-ä")), None);
 }
 
 #[test]
 fn test_random() {
-    let pairs: &[(u32, usize, &[usize])] = &[
+    let pairs: &[(u32, usize, &[u64])] = &[
         (
             510747253,
             2498515,
@@ -244,3 +832,280 @@ fn test_random() {
         });
     }
 }
+
+#[cfg(test)]
+pub(crate) use crate::sample::sample_bundle;
+
+/// [`sample_bundle`] of `size` bytes encrypted for `guid`, returns the bundle, the data and a
+/// key fragment of `key_len` bytes.
+#[cfg(test)]
+pub(crate) fn encrypted_sample(
+    guid: &[u8],
+    size: usize,
+    key_len: usize,
+) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let plain = sample_bundle(size);
+    let mut bytes = vec![0; size - key_len];
+    let mut key = vec![0; key_len];
+    try_encrypt(guid, &plain, &mut bytes, &mut key).unwrap();
+    (plain, bytes, key)
+}
+
+#[test]
+fn test_round_trip() {
+    let guids = [
+        "2c99f767-53b9-463c-aa99-791b04cd9003",
+        "6b86cced-e17c-4f57-8bdf-812615773ce6",
+        "9d1d8585-9c0b-40d9-8721-76f21cc745f2",
+    ];
+    for guid in guids {
+        for (size, key_len) in [(2498515, 1024), (690036, 16), (5000, 4096), (123457, 0)] {
+            let (plain, bytes, key) = encrypted_sample(guid.as_bytes(), size, key_len);
+
+            let mut dec = vec![0x42; size];
+            unsafe { decrypt_internal(guid.as_bytes(), &bytes, &key, &mut dec) };
+            assert!(plain == dec);
+        }
+    }
+}
+
+#[test]
+fn test_try_decrypt() {
+    let guid = b"2c99f767-53b9-463c-aa99-791b04cd9003";
+    let (plain, bytes, key) = encrypted_sample(guid, 690036, 512);
+
+    let mut dec = vec![0; plain.len() + 16];
+    assert_eq!(try_decrypt(guid, &bytes, &key, &mut dec), Ok(()));
+    assert!(dec[..plain.len()] == plain[..]);
+
+    assert_eq!(
+        try_decrypt(guid, &bytes, &key, &mut dec[..plain.len() - 1]),
+        Err(DecryptError::DstTooSmall)
+    );
+    assert_eq!(
+        try_decrypt(b"", &bytes, &key, &mut dec),
+        Err(DecryptError::ZeroLength)
+    );
+    assert_eq!(
+        try_decrypt(guid, &[], &[], &mut dec),
+        Err(DecryptError::ZeroLength)
+    );
+
+    // This GUID makes the PRNG emit 101 segments for 1 MB
+    let guid = b"00000048-0000-0000-0000-000000000000";
+    let bytes = vec![0; 1_000_000];
+    let mut dec = vec![0; bytes.len()];
+    assert_eq!(
+        try_decrypt(guid, &bytes, &[], &mut dec),
+        Err(DecryptError::SegmentOverflow)
+    );
+}
+
+#[test]
+fn test_try_encrypt() {
+    let guid = b"2c99f767-53b9-463c-aa99-791b04cd9003";
+    let plain = sample_bundle(690036);
+    let mut bytes = vec![0; plain.len() - 512];
+    let mut key = vec![0; 512];
+    assert_eq!(try_encrypt(guid, &plain, &mut bytes, &mut key), Ok(()));
+    let mut dec = vec![0; plain.len()];
+    assert_eq!(try_decrypt(guid, &bytes, &key, &mut dec), Ok(()));
+    assert!(dec == plain);
+
+    assert_eq!(
+        try_encrypt(guid, &plain, &mut bytes, &mut key[1..]),
+        Err(DecryptError::SizeMismatch)
+    );
+    assert_eq!(
+        try_encrypt(guid, &plain[..512], &mut [], &mut key),
+        Err(DecryptError::ZeroLength)
+    );
+    assert_eq!(
+        try_encrypt(b"", &plain, &mut bytes, &mut key),
+        Err(DecryptError::ZeroLength)
+    );
+
+    let guid = b"00000048-0000-0000-0000-000000000000";
+    let src = vec![0; 1_000_000];
+    let mut bytes = vec![0; src.len()];
+    assert_eq!(
+        try_encrypt(guid, &src, &mut bytes, &mut []),
+        Err(DecryptError::SegmentOverflow)
+    );
+}
+
+#[test]
+fn test_decrypt_in_place() {
+    let guids = [
+        "8611ee9e-0c57-48d2-af32-7f980b0895db",
+        "32ceb35d-24fa-469f-8aa4-23851ac68f84",
+        "67e08c5c-d918-478e-ad8d-58e884fa53b4",
+    ];
+    for guid in guids {
+        for (size, key_len) in [(6442418, 2048), (1227329, 100), (1500, 10), (999, 0)] {
+            let (plain, bytes, key) = encrypted_sample(guid.as_bytes(), size, key_len);
+            let mut buf = [bytes, key].concat();
+
+            assert_eq!(decrypt_in_place(guid.as_bytes(), &mut buf), Ok(()));
+            assert!(buf == plain);
+        }
+    }
+}
+
+#[test]
+fn test_segment_plan() {
+    let guid = b"5dc14ba2-7164-40e9-8b52-f55cf3129a24";
+    let plan = SegmentPlan::new(guid, 2974248).unwrap();
+    assert_eq!(plan.total_size(), 2974248);
+
+    // The first segment holds the UnityFS header and is never moved
+    let first = plan.iter().next().unwrap();
+    assert_eq!((first.src, first.dst), (0, 0));
+
+    // Pinned, so 32-bit and wasm builds have to scramble the same way
+    let order: Vec<_> = plan.iter().take(8).map(|segment| segment.dst).collect();
+    assert_eq!(
+        order,
+        [0, 1858221, 2617395, 2053185, 1265847, 1179621, 652221, 2696103]
+    );
+
+    // Sources are back to back, destinations cover the bundle exactly once
+    let mut src = 0;
+    let mut dst: Vec<_> = plan
+        .iter()
+        .map(|segment| {
+            assert_eq!(segment.src, src);
+            src += segment.len;
+            (segment.dst, segment.len)
+        })
+        .collect();
+    dst.sort();
+    assert_eq!(
+        dst.iter().fold(0, |end, &(dst, len)| {
+            assert_eq!(end, dst);
+            dst + len
+        }),
+        2974248
+    );
+
+    assert!(SegmentPlan::new(guid, 0).unwrap().is_empty());
+}
+
+#[test]
+fn test_try_decrypt_hashed() {
+    use xxhash_rust::xxh3::xxh3_128;
+
+    let guid = b"6586c486-4731-4fae-a2d2-de415cd8bcd6";
+    let (plain, bytes, key) = encrypted_sample(guid, 912345, 1234);
+
+    let want = xxh3_128(&plain);
+    let mut dst = vec![0; plain.len()];
+    assert_eq!(
+        try_decrypt_hashed(guid, &bytes, &key, &mut dst, None),
+        Ok(want)
+    );
+    assert!(dst == plain);
+
+    let mut dst = vec![0; plain.len()];
+    assert_eq!(
+        try_decrypt_hashed(guid, &bytes, &key, &mut dst, Some(want)),
+        Ok(want)
+    );
+    assert_eq!(
+        try_decrypt_hashed(guid, &bytes, &key, &mut dst, Some(want ^ 1)),
+        Err(DecryptError::HashMismatch)
+    );
+    assert!(dst == plain);
+    assert_eq!(
+        try_decrypt_hashed(guid, &bytes, &key, &mut dst[..1000], None),
+        Err(DecryptError::DstTooSmall)
+    );
+}
+
+#[test]
+fn test_try_decrypt_range() {
+    let guid = b"0f3b8a4e-96d2-4c1b-b5e7-2d8c9a71f064";
+    let (plain, bytes, key) = encrypted_sample(guid, 1834567, 3000);
+
+    let plan = SegmentPlan::new(guid, plain.len()).unwrap();
+    let len = plain.len();
+    for range in [0..4096, 0..0, 12345..654321, len - 2500..len, 0..len] {
+        // Only the source bytes of the window are needed
+        let mut sparse_bytes = vec![0xee; bytes.len()];
+        let mut sparse_key = vec![0xee; key.len()];
+        for segment in plan.window(range.clone()) {
+            for src in segment.src..segment.src + segment.len {
+                match src.checked_sub(bytes.len()) {
+                    None => sparse_bytes[src] = bytes[src],
+                    Some(src) => sparse_key[src] = key[src],
+                }
+            }
+        }
+        let needed: usize = plan.window(range.clone()).map(|segment| segment.len).sum();
+        assert_eq!(needed, range.len());
+
+        let mut dst = vec![0; range.len()];
+        assert_eq!(
+            try_decrypt_range(guid, &sparse_bytes, &sparse_key, range.clone(), &mut dst),
+            Ok(())
+        );
+        assert!(dst[..] == plain[range]);
+    }
+
+    let mut dst = vec![0; 100];
+    assert_eq!(
+        try_decrypt_range(guid, &bytes, &key, len - 50..len + 50, &mut dst),
+        Err(DecryptError::RangeOutOfBounds)
+    );
+    assert_eq!(
+        try_decrypt_range(guid, &bytes, &key, 0..101, &mut dst),
+        Err(DecryptError::DstTooSmall)
+    );
+}
+
+#[test]
+fn test_try_decrypt_progress() {
+    let guid = b"5c2e7a91-3d4f-4b8e-9a06-e1f7b2c48d53";
+    let (plain, bytes, key) = encrypted_sample(guid, 2345678, 2000);
+
+    let mut dst = vec![0; plain.len()];
+    let mut reports = Vec::new();
+    let result = try_decrypt_progress(guid, &bytes, &key, &mut dst, 100_000, |done, total| {
+        reports.push((done, total));
+        true
+    });
+    assert_eq!(result, Ok(()));
+    assert!(dst == plain);
+    let expected: Vec<_> = (1..=23)
+        .map(|i| i * 100_000)
+        .chain([plain.len()])
+        .map(|done| (done, plain.len()))
+        .collect();
+    assert_eq!(reports, expected);
+
+    // Only the end is reported
+    let mut reports = 0;
+    let result = try_decrypt_progress(guid, &bytes, &key, &mut dst, 0, |done, total| {
+        assert_eq!(done, total);
+        reports += 1;
+        true
+    });
+    assert_eq!((result, reports), (Ok(()), 1));
+
+    // Stopping leaves a decrypted prefix and the rest untouched
+    let mut dst = vec![0xaa; plain.len()];
+    let mut stopped_at = 0;
+    let result = try_decrypt_progress(guid, &bytes, &key, &mut dst, 65536, |done, _| {
+        stopped_at = done;
+        done < 500_000
+    });
+    assert_eq!(result, Err(DecryptError::Aborted));
+    assert_eq!(stopped_at, 524_288);
+    assert!(dst[..stopped_at] == plain[..stopped_at]);
+    assert!(dst[stopped_at..].iter().all(|&b| b == 0xaa));
+
+    assert_eq!(
+        try_decrypt_progress(guid, &bytes, &key, &mut dst[..1000], 1, |_, _| true),
+        Err(DecryptError::DstTooSmall)
+    );
+}
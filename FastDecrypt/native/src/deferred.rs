@@ -0,0 +1,171 @@
+use crate::decrypt::{DecryptError, SegmentPlan};
+
+/// Decrypts a bundle in two steps, for when the key fragment arrives after the body.
+///
+/// [`Self::feed_body`] copies every segment, or part of one, that comes from the body, which
+/// is all but the last `total_size - bytes.len()` source bytes. [`Self::feed_key`] fills in the
+/// rest once the key fragment is there. `dst` has to be the same buffer for both.
+pub struct Decryptor {
+    plan: SegmentPlan,
+    body_len: Option<usize>,
+}
+
+impl Decryptor {
+    pub fn new(guid: &[u8], total_size: usize) -> Result<Self, DecryptError> {
+        if guid.is_empty() {
+            return Err(DecryptError::ZeroLength);
+        }
+        Ok(Self {
+            plan: SegmentPlan::new(guid, total_size)?,
+            body_len: None,
+        })
+    }
+
+    /// Decryptor whose body of `body_len` bytes was already fed, by another one or in an
+    /// earlier call over the C ABI.
+    pub fn resume(guid: &[u8], total_size: usize, body_len: usize) -> Result<Self, DecryptError> {
+        if body_len == 0 {
+            return Err(DecryptError::ZeroLength);
+        }
+        if body_len > total_size {
+            return Err(DecryptError::SizeMismatch);
+        }
+        Ok(Self {
+            body_len: Some(body_len),
+            ..Self::new(guid, total_size)?
+        })
+    }
+
+    /// Size of the bundle the decryptor was made for.
+    pub fn total_size(&self) -> usize {
+        self.plan.total_size()
+    }
+
+    /// Copy the parts of the bundle that come from `bytes` into `dst`.
+    ///
+    /// Returns [`DecryptError::SizeMismatch`] if `bytes` is longer than the bundle.
+    pub fn feed_body(&mut self, bytes: &[u8], dst: &mut [u8]) -> Result<(), DecryptError> {
+        if bytes.is_empty() {
+            return Err(DecryptError::ZeroLength);
+        }
+        if bytes.len() > self.total_size() {
+            return Err(DecryptError::SizeMismatch);
+        }
+        let dst = dst
+            .get_mut(..self.total_size())
+            .ok_or(DecryptError::DstTooSmall)?;
+
+        copy_body(&self.plan, bytes, dst);
+        self.body_len = Some(bytes.len());
+        Ok(())
+    }
+
+    /// Copy the parts of the bundle that come from `key_frag` into `dst`, which completes it.
+    ///
+    /// Returns [`DecryptError::MissingBody`] if the body wasn't fed yet and
+    /// [`DecryptError::SizeMismatch`] if `key_frag` doesn't complete it to the planned size.
+    pub fn feed_key(&self, key_frag: &[u8], dst: &mut [u8]) -> Result<(), DecryptError> {
+        let body_len = self.body_len.ok_or(DecryptError::MissingBody)?;
+        if body_len.checked_add(key_frag.len()) != Some(self.total_size()) {
+            return Err(DecryptError::SizeMismatch);
+        }
+        let dst = dst
+            .get_mut(..self.total_size())
+            .ok_or(DecryptError::DstTooSmall)?;
+
+        copy_key(&self.plan, body_len, key_frag, dst);
+        Ok(())
+    }
+}
+
+/// Copy the parts of the segments with their source in `bytes`.
+fn copy_body(plan: &SegmentPlan, bytes: &[u8], dst: &mut [u8]) {
+    for segment in plan.iter().filter(|segment| segment.src < bytes.len()) {
+        let len = segment.len.min(bytes.len() - segment.src);
+        dst[segment.dst..][..len].copy_from_slice(&bytes[segment.src..][..len]);
+    }
+}
+
+/// Copy the parts of the segments with their source in `key_frag`, which follows `body_len`
+/// bytes of body.
+fn copy_key(plan: &SegmentPlan, body_len: usize, key_frag: &[u8], dst: &mut [u8]) {
+    for segment in plan
+        .iter()
+        .filter(|segment| segment.src + segment.len > body_len)
+    {
+        // Part of a segment may come from the body
+        let skip = body_len.saturating_sub(segment.src);
+        let len = segment.len - skip;
+        let src = segment.src + skip - body_len;
+        dst[segment.dst + skip..][..len].copy_from_slice(&key_frag[src..][..len]);
+    }
+}
+
+#[test]
+fn test_decryptor() {
+    use crate::decrypt::encrypted_sample;
+
+    let guid = b"a4f0c2d7-81b3-4e5a-9c6d-0b7e3f218a95";
+    let (plain, bytes, key) = encrypted_sample(guid, 3456789, 60_000);
+
+    let mut decryptor = Decryptor::new(guid, plain.len()).unwrap();
+    let mut dst = vec![0xaa; plain.len()];
+    assert_eq!(
+        decryptor.feed_key(&key, &mut dst),
+        Err(DecryptError::MissingBody)
+    );
+    assert_eq!(decryptor.feed_body(&bytes, &mut dst), Ok(()));
+
+    // Everything but the bytes from the key fragment is in place
+    let missing: usize = SegmentPlan::new(guid, plain.len())
+        .unwrap()
+        .iter()
+        .filter(|segment| segment.src + segment.len > bytes.len())
+        .map(|segment| {
+            let skip = bytes.len().saturating_sub(segment.src);
+            let from_key = segment.dst + skip..segment.dst + segment.len;
+            assert!(dst[from_key.clone()].iter().all(|&b| b == 0xaa));
+            dst[from_key.clone()].copy_from_slice(&plain[from_key.clone()]);
+            from_key.len()
+        })
+        .sum();
+    assert_eq!(missing, key.len());
+    assert!(dst == plain);
+
+    dst.fill(0xaa);
+    assert_eq!(decryptor.feed_body(&bytes, &mut dst), Ok(()));
+    assert_eq!(decryptor.feed_key(&key, &mut dst), Ok(()));
+    assert!(dst == plain);
+
+    // The key fragment alone, after the body went through another decryptor
+    dst.fill(0xaa);
+    assert_eq!(decryptor.feed_body(&bytes, &mut dst), Ok(()));
+    let resumed = Decryptor::resume(guid, plain.len(), bytes.len()).unwrap();
+    assert_eq!(resumed.feed_key(&key, &mut dst), Ok(()));
+    assert!(dst == plain);
+
+    assert_eq!(
+        decryptor.feed_key(&key[1..], &mut dst),
+        Err(DecryptError::SizeMismatch)
+    );
+    assert_eq!(
+        decryptor.feed_key(&key, &mut dst[1..]),
+        Err(DecryptError::DstTooSmall)
+    );
+    assert_eq!(
+        decryptor.feed_body(&plain, &mut [0; 1]),
+        Err(DecryptError::DstTooSmall)
+    );
+    assert_eq!(
+        decryptor.feed_body(&[plain.as_slice(), &[0]].concat(), &mut dst),
+        Err(DecryptError::SizeMismatch)
+    );
+
+    // Nothing left for the key, the body is the whole bundle
+    let mut whole = Decryptor::new(guid, plain.len()).unwrap();
+    let (_, body, _) = encrypted_sample(guid, plain.len(), 0);
+    let mut dst = vec![0; plain.len()];
+    assert_eq!(whole.feed_body(&body, &mut dst), Ok(()));
+    assert_eq!(whole.feed_key(&[], &mut dst), Ok(()));
+    assert!(dst == plain);
+}
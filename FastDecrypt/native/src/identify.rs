@@ -0,0 +1,182 @@
+use core::ops::Range;
+
+use crate::decompress::{block_compression, decompress_block, parse_bundle};
+use crate::decrypt::{copy_from, DecryptError, SegmentPlan};
+use crate::unityfs::{Header, BLOCKS_INFO_AT_END, COMPRESSION_NONE, HEADER_PROBE_LEN};
+
+/// Find which of `candidates` the input was encrypted with, returns its index.
+///
+/// The UnityFS header sits in the first segment, which keeps its place for every GUID, so it
+/// can't tell the candidates apart. Instead only the header, the blocks info and the first
+/// compressed block reaching past the segments that stay in place are reassembled for each
+/// candidate, and that block has to decompress to exactly its size.
+///
+/// Bundles with only uncompressed blocks past the first segment never match. LZMA bundles have
+/// a single block, so for them every candidate costs a full decompression.
+pub fn identify<G: AsRef<[u8]>>(
+    candidates: &[G],
+    bytes: &[u8],
+    key_frag: &[u8],
+) -> Result<Option<usize>, DecryptError> {
+    if bytes.is_empty() {
+        return Err(DecryptError::ZeroLength);
+    }
+    let total_size = bytes
+        .len()
+        .checked_add(key_frag.len())
+        .ok_or(DecryptError::LengthOverflow)?;
+
+    Ok(candidates
+        .iter()
+        .position(|guid| matches(guid.as_ref(), bytes, key_frag, total_size)))
+}
+
+fn matches(guid: &[u8], bytes: &[u8], key_frag: &[u8], total_size: usize) -> bool {
+    if guid.is_empty() {
+        return false;
+    }
+    let Ok(plan) = SegmentPlan::new(guid, total_size) else {
+        return false;
+    };
+    let read = |range: Range<usize>| {
+        let mut out = vec![0; range.len()];
+        for segment in plan.window(range.clone()) {
+            let dst = &mut out[segment.dst - range.start..][..segment.len];
+            copy_from(bytes, key_frag, segment.src, dst);
+        }
+        out
+    };
+
+    // Header and blocks info, laid out so `Bundle::parse_with` finds the blocks info where it
+    // expects it
+    let head = read(0..HEADER_PROBE_LEN.min(total_size));
+    let Some((header, mut header_len)) = Header::parse(&head) else {
+        return false;
+    };
+    if header.size != total_size as u64 {
+        return false;
+    }
+    if header.version >= 7 {
+        header_len = header_len.next_multiple_of(16);
+    }
+    let info_len = header.compressed_blocks_info_size as usize;
+    let Some(prefix_len) = header_len.checked_add(info_len) else {
+        return false;
+    };
+    if prefix_len > total_size {
+        return false;
+    }
+    let prefix = match header.flags & BLOCKS_INFO_AT_END {
+        0 => read(0..prefix_len),
+        _ => [read(0..header_len), read(total_size - info_len..total_size)].concat(),
+    };
+    let mut scratch = Vec::new();
+    let Ok(bundle) = parse_bundle(&prefix, &mut scratch) else {
+        return false;
+    };
+
+    // Segments at the start that stay in place look the same for every candidate
+    let fixed_end = plan
+        .iter()
+        .take_while(|segment| segment.src == segment.dst)
+        .last()
+        .map_or(0, |segment| segment.dst + segment.len);
+
+    let mut src = bundle.data_offset;
+    for block in bundle.blocks() {
+        let end = src + block.compressed_size as usize;
+        if end > total_size {
+            return false;
+        }
+        let Ok(compression) = block_compression(&block) else {
+            return false;
+        };
+        if end > fixed_end && compression != COMPRESSION_NONE {
+            let mut output = vec![0; block.uncompressed_size as usize];
+            return decompress_block(compression, &read(src..end), &mut output).is_ok();
+        }
+        src = end;
+    }
+    false
+}
+
+/// Encode `data` as LZ4 with a match every 16 bytes, `data` has to repeat every 8 bytes of
+/// each 16 byte chunk.
+#[cfg(test)]
+fn compress_pairs(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let chunks = data.chunks_exact(16);
+    let rest = chunks.remainder();
+    for chunk in chunks {
+        // 8 literals, then the same 8 bytes again as a match 8 back
+        out.push(0x84);
+        out.extend_from_slice(&chunk[..8]);
+        out.extend(8u16.to_le_bytes());
+    }
+    out.extend(crate::lz4::compress_literals(rest));
+    out
+}
+
+#[test]
+fn test_identify() {
+    use crate::decrypt::try_encrypt;
+    use crate::unityfs::{sample_bundle_file, BlockInfo, Node, COMPRESSION_LZ4HC};
+
+    let mut state = 0x9e3779b97f4a7c15u64;
+    let plain: Vec<u8> = (0..400_000)
+        .flat_map(|i| {
+            if i % 2 == 0 {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+            }
+            state.to_le_bytes()
+        })
+        .take(400_000)
+        .collect();
+    let chunks: Vec<_> = plain.chunks(131_072).collect();
+    let stored: Vec<_> = chunks.iter().map(|chunk| compress_pairs(chunk)).collect();
+    let blocks: Vec<_> = chunks
+        .iter()
+        .zip(&stored)
+        .map(|(chunk, stored)| BlockInfo {
+            uncompressed_size: chunk.len() as u32,
+            compressed_size: stored.len() as u32,
+            flags: COMPRESSION_LZ4HC as u16,
+        })
+        .collect();
+    let nodes = [Node {
+        offset: 0,
+        size: plain.len() as u64,
+        flags: 4,
+        path: b"CAB-0",
+    }];
+
+    let candidates = [
+        "8611ee9e-0c57-48d2-af32-7f980b0895db",
+        "32ceb35d-24fa-469f-8aa4-23851ac68f84",
+        "",
+        "67e08c5c-d918-478e-ad8d-58e884fa53b4",
+    ];
+    for at_end in [false, true] {
+        let data = stored.concat();
+        let file = sample_bundle_file(&blocks, &nodes, &data, COMPRESSION_LZ4HC, at_end);
+        for (index, guid) in candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, guid)| !guid.is_empty())
+        {
+            let mut bytes = vec![0; file.len() - 100];
+            let mut key = vec![0; 100];
+            try_encrypt(guid.as_bytes(), &file, &mut bytes, &mut key).unwrap();
+
+            assert_eq!(identify(&candidates, &bytes, &key), Ok(Some(index)));
+            assert_eq!(identify(&candidates[..index], &bytes, &key), Ok(None));
+            assert_eq!(identify(&candidates, &bytes, &key[..99]), Ok(None));
+        }
+    }
+    assert_eq!(
+        identify(&candidates, &[], &[]),
+        Err(DecryptError::ZeroLength)
+    );
+}
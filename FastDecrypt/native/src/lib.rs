@@ -1,12 +1,15 @@
-#![no_std]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
 
 #[panic_handler]
-#[cfg(not(test))]
+#[cfg(all(feature = "runtime", not(any(test, feature = "std"))))]
 fn panic(_: &core::panic::PanicInfo) -> ! {
     loop {}
 }
 
-#[cfg(target_env = "msvc")]
+#[cfg(all(target_env = "msvc", feature = "runtime", not(feature = "std")))]
 mod msvc {
     #[link(name = "vcruntime")]
     extern "C" {}
@@ -18,22 +21,31 @@ mod msvc {
     extern "system" fn __chkstk() {}
 
     #[no_mangle]
-    extern "system" fn _DllMainCRTStartup(_: *const u8, _: u32, _: *const u8) -> u32 {
+    extern "system" fn _DllMainCRTStartup(_: *const u8, _reason: u32, _: *const u8) -> u32 {
+        #[cfg(feature = "boehm")]
+        if _reason == 1 {
+            unsafe { crate::mono::register() };
+        }
         1
     }
 }
 
-#[cfg(target_env = "gnu")]
+#[cfg(all(windows, target_env = "gnu", feature = "runtime", not(feature = "std")))]
 mod mingw {
     #[no_mangle]
-    extern "system" fn DllMainCRTStartup(_: *const u8, _: u32, _: *const u8) -> u32 {
+    extern "system" fn DllMainCRTStartup(_: *const u8, _reason: u32, _: *const u8) -> u32 {
+        #[cfg(feature = "boehm")]
+        if _reason == 1 {
+            unsafe { crate::mono::register() };
+        }
         1
     }
 }
 
 /// # Safety
 ///
-/// We have to trust the caller to supply valid ptr/sz pairs to the function.
+/// We have to trust the caller to supply valid ptr/sz pairs to the function, and the same
+/// input [`crate::decrypt::decrypt_internal`] requires. Use [`try_decrypt`] for untrusted bundles.
 #[no_mangle]
 pub unsafe extern "C" fn decrypt(
     guid_ptr: *const u8,
@@ -49,7 +61,1019 @@ pub unsafe extern "C" fn decrypt(
     let key = core::slice::from_raw_parts(key_ptr, key_len);
     let dst = core::slice::from_raw_parts_mut(dst_ptr, data_len + key_len);
 
-    decrypt::decrypt_internal(&guid, data, key, dst);
+    decrypt::decrypt_internal(guid, data, key, dst);
+}
+
+/// In-place variant of [`decrypt`], returns 0 on success or a [`decrypt::DecryptError`].
+///
+/// `buf_ptr` holds the data immediately followed by the key fragment and receives the
+/// decrypted bundle.
+///
+/// # Safety
+///
+/// We have to trust the caller to supply a valid ptr/sz pair to the function.
+#[no_mangle]
+pub unsafe extern "C" fn decrypt_in_place(
+    guid_ptr: *const u8,
+    guid_len: usize,
+    buf_ptr: *mut u8,
+    data_len: usize,
+    key_len: usize,
+) -> u32 {
+    if guid_ptr.is_null() || buf_ptr.is_null() {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    let Some(buf_len) = data_len.checked_add(key_len) else {
+        return decrypt::DecryptError::LengthOverflow as u32;
+    };
+    let guid = core::slice::from_raw_parts(guid_ptr, guid_len);
+    let buf = core::slice::from_raw_parts_mut(buf_ptr, buf_len);
+
+    match decrypt::decrypt_in_place(guid, buf) {
+        Ok(()) => 0,
+        Err(err) => err as u32,
+    }
+}
+
+/// Key fragment from a ptr/sz pair, a null `ptr` is accepted if `len` is 0.
+///
+/// # Safety
+///
+/// A non-null `ptr` has to point to `len` bytes.
+pub(crate) unsafe fn key_slice<'a>(
+    ptr: *const u8,
+    len: usize,
+) -> Result<&'a [u8], decrypt::DecryptError> {
+    match (ptr.is_null(), len) {
+        (true, 0) => Ok(&[]),
+        (true, _) => Err(decrypt::DecryptError::NullPointer),
+        (false, _) => Ok(core::slice::from_raw_parts(ptr, len)),
+    }
+}
+
+/// Mutable [`key_slice`], for the key fragments written by [`try_encrypt`] and [`rekey`].
+///
+/// # Safety
+///
+/// See [`key_slice`].
+unsafe fn key_slice_mut<'a>(
+    ptr: *mut u8,
+    len: usize,
+) -> Result<&'a mut [u8], decrypt::DecryptError> {
+    match (ptr.is_null(), len) {
+        (true, 0) => Ok(&mut []),
+        (true, _) => Err(decrypt::DecryptError::NullPointer),
+        (false, _) => Ok(core::slice::from_raw_parts_mut(ptr, len)),
+    }
+}
+
+/// Checked variant of [`decrypt`], returns 0 on success or a [`decrypt::DecryptError`].
+///
+/// # Safety
+///
+/// Pointers are checked for null, but otherwise we have to trust the caller to supply valid
+/// ptr/sz pairs to the function. A null `key_ptr` is accepted if `key_len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn try_decrypt(
+    guid_ptr: *const u8,
+    guid_len: usize,
+    data_ptr: *const u8,
+    data_len: usize,
+    key_ptr: *const u8,
+    key_len: usize,
+    dst_ptr: *mut u8,
+    dst_len: usize,
+) -> u32 {
+    if guid_ptr.is_null() || data_ptr.is_null() || dst_ptr.is_null() {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    let key = match key_slice(key_ptr, key_len) {
+        Ok(key) => key,
+        Err(err) => return err as u32,
+    };
+    let guid = core::slice::from_raw_parts(guid_ptr, guid_len);
+    let data = core::slice::from_raw_parts(data_ptr, data_len);
+    let dst = core::slice::from_raw_parts_mut(dst_ptr, dst_len);
+
+    match decrypt::try_decrypt(guid, data, key, dst) {
+        Ok(()) => 0,
+        Err(err) => err as u32,
+    }
+}
+
+/// Same as [`try_decrypt`], but also computes the XXH128 of the output while copying it.
+///
+/// The hash is exchanged in its canonical big endian byte order, the same `xxhsum -H2` prints.
+/// If `expected_ptr` isn't null, it has to point to 16 bytes and a different hash fails with
+/// [`decrypt::DecryptError::HashMismatch`]. If `hash_ptr` isn't null, the hash is written to
+/// its 16 bytes on success and on a mismatch.
+///
+/// # Safety
+///
+/// See [`try_decrypt`].
+#[no_mangle]
+pub unsafe extern "C" fn try_decrypt_hashed(
+    guid_ptr: *const u8,
+    guid_len: usize,
+    data_ptr: *const u8,
+    data_len: usize,
+    key_ptr: *const u8,
+    key_len: usize,
+    dst_ptr: *mut u8,
+    dst_len: usize,
+    expected_ptr: *const u8,
+    hash_ptr: *mut u8,
+) -> u32 {
+    if guid_ptr.is_null() || data_ptr.is_null() || dst_ptr.is_null() {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    let key = match key_slice(key_ptr, key_len) {
+        Ok(key) => key,
+        Err(err) => return err as u32,
+    };
+    let guid = core::slice::from_raw_parts(guid_ptr, guid_len);
+    let data = core::slice::from_raw_parts(data_ptr, data_len);
+    let dst = core::slice::from_raw_parts_mut(dst_ptr, dst_len);
+    let expected = match expected_ptr.is_null() {
+        true => None,
+        false => Some(u128::from_be_bytes(
+            expected_ptr.cast::<[u8; 16]>().read_unaligned(),
+        )),
+    };
+
+    // Compared here, so the hash is handed out on a mismatch as well
+    let hash = match decrypt::try_decrypt_hashed(guid, data, key, dst, None) {
+        Ok(hash) => hash,
+        Err(err) => return err as u32,
+    };
+    if !hash_ptr.is_null() {
+        hash_ptr
+            .cast::<[u8; 16]>()
+            .write_unaligned(hash.to_be_bytes());
+    }
+    match expected {
+        Some(expected) if expected != hash => decrypt::DecryptError::HashMismatch as u32,
+        _ => 0,
+    }
+}
+
+/// Same as [`try_decrypt`], but also checks the UnityFS header of the output.
+///
+/// Truncated or corrupt input is reported as [`decrypt::DecryptError::BadSignature`],
+/// [`decrypt::DecryptError::UnsupportedVersion`] or [`decrypt::DecryptError::SizeMismatch`]. A
+/// wrong GUID usually isn't, see [`unityfs::validate`].
+///
+/// # Safety
+///
+/// See [`try_decrypt`].
+#[no_mangle]
+pub unsafe extern "C" fn try_decrypt_validated(
+    guid_ptr: *const u8,
+    guid_len: usize,
+    data_ptr: *const u8,
+    data_len: usize,
+    key_ptr: *const u8,
+    key_len: usize,
+    dst_ptr: *mut u8,
+    dst_len: usize,
+) -> u32 {
+    match try_decrypt(
+        guid_ptr, guid_len, data_ptr, data_len, key_ptr, key_len, dst_ptr, dst_len,
+    ) {
+        0 => {}
+        status => return status,
+    }
+    let bundle = core::slice::from_raw_parts(dst_ptr, data_len + key_len);
+
+    match unityfs::validate(bundle) {
+        Ok(_) => 0,
+        Err(err) => err as u32,
+    }
+}
+
+/// Same as [`try_decrypt`] for the scheme with id `scheme`, see [`scheme::Scheme`].
+///
+/// # Safety
+///
+/// See [`try_decrypt`].
+#[no_mangle]
+pub unsafe extern "C" fn try_decrypt_scheme(
+    scheme: u32,
+    guid_ptr: *const u8,
+    guid_len: usize,
+    data_ptr: *const u8,
+    data_len: usize,
+    key_ptr: *const u8,
+    key_len: usize,
+    dst_ptr: *mut u8,
+    dst_len: usize,
+) -> u32 {
+    if guid_ptr.is_null() || data_ptr.is_null() || dst_ptr.is_null() {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    let key = match key_slice(key_ptr, key_len) {
+        Ok(key) => key,
+        Err(err) => return err as u32,
+    };
+    let guid = core::slice::from_raw_parts(guid_ptr, guid_len);
+    let data = core::slice::from_raw_parts(data_ptr, data_len);
+    let dst = core::slice::from_raw_parts_mut(dst_ptr, dst_len);
+
+    let result = scheme::Scheme::from_id(scheme)
+        .and_then(|scheme| decrypt::try_decrypt_with(scheme, guid, data, key, dst));
+    match result {
+        Ok(()) => 0,
+        Err(err) => err as u32,
+    }
+}
+
+/// Same as [`try_decrypt`], but only decrypts bytes `start..end` of the bundle into `dst_ptr`,
+/// which has to hold `end - start` bytes.
+///
+/// Returns [`decrypt::DecryptError::RangeOutOfBounds`] if the range isn't within the bundle.
+///
+/// # Safety
+///
+/// See [`try_decrypt`].
+#[no_mangle]
+pub unsafe extern "C" fn try_decrypt_range(
+    guid_ptr: *const u8,
+    guid_len: usize,
+    data_ptr: *const u8,
+    data_len: usize,
+    key_ptr: *const u8,
+    key_len: usize,
+    start: usize,
+    end: usize,
+    dst_ptr: *mut u8,
+    dst_len: usize,
+) -> u32 {
+    if guid_ptr.is_null() || data_ptr.is_null() || dst_ptr.is_null() {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    let key = match key_slice(key_ptr, key_len) {
+        Ok(key) => key,
+        Err(err) => return err as u32,
+    };
+    let guid = core::slice::from_raw_parts(guid_ptr, guid_len);
+    let data = core::slice::from_raw_parts(data_ptr, data_len);
+    let dst = core::slice::from_raw_parts_mut(dst_ptr, dst_len);
+
+    match decrypt::try_decrypt_range(guid, data, key, start..end, dst) {
+        Ok(()) => 0,
+        Err(err) => err as u32,
+    }
+}
+
+/// Progress callback for [`try_decrypt_progress`], receives the bytes done and the total.
+/// Returns 0 to continue, anything else stops the decryption.
+pub type ProgressCallback =
+    unsafe extern "C" fn(ctx: *mut core::ffi::c_void, done: usize, total: usize) -> u32;
+
+/// Same as [`try_decrypt`], but calls `progress` every `interval` bytes and at the end.
+///
+/// If `progress` asks to stop, [`decrypt::DecryptError::Aborted`] is returned. The bytes done
+/// so far are then decrypted at the start of `dst_ptr`, the rest is left as it was.
+///
+/// # Safety
+///
+/// See [`try_decrypt`].
+#[no_mangle]
+pub unsafe extern "C" fn try_decrypt_progress(
+    guid_ptr: *const u8,
+    guid_len: usize,
+    data_ptr: *const u8,
+    data_len: usize,
+    key_ptr: *const u8,
+    key_len: usize,
+    dst_ptr: *mut u8,
+    dst_len: usize,
+    interval: usize,
+    progress: ProgressCallback,
+    ctx: *mut core::ffi::c_void,
+) -> u32 {
+    if guid_ptr.is_null() || data_ptr.is_null() || dst_ptr.is_null() {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    let key = match key_slice(key_ptr, key_len) {
+        Ok(key) => key,
+        Err(err) => return err as u32,
+    };
+    let guid = core::slice::from_raw_parts(guid_ptr, guid_len);
+    let data = core::slice::from_raw_parts(data_ptr, data_len);
+    let dst = core::slice::from_raw_parts_mut(dst_ptr, dst_len);
+
+    let result = decrypt::try_decrypt_progress(guid, data, key, dst, interval, |done, total| {
+        progress(ctx, done, total) == 0
+    });
+    match result {
+        Ok(()) => 0,
+        Err(err) => err as u32,
+    }
+}
+
+/// First half of a decryption whose key fragment arrives later, returns 0 on success or a
+/// [`decrypt::DecryptError`].
+///
+/// Copies everything that comes from the `data_len` bytes of data into `dst_ptr`, a bundle of
+/// `data_len + key_len` bytes. [`decrypt_key`] completes it into the same buffer.
+///
+/// # Safety
+///
+/// Pointers are checked for null, but otherwise we have to trust the caller to supply valid
+/// ptr/sz pairs to the function.
+#[no_mangle]
+pub unsafe extern "C" fn decrypt_body(
+    guid_ptr: *const u8,
+    guid_len: usize,
+    data_ptr: *const u8,
+    data_len: usize,
+    key_len: usize,
+    dst_ptr: *mut u8,
+    dst_len: usize,
+) -> u32 {
+    if guid_ptr.is_null() || data_ptr.is_null() || dst_ptr.is_null() {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    let Some(total_size) = data_len.checked_add(key_len) else {
+        return decrypt::DecryptError::LengthOverflow as u32;
+    };
+    let guid = core::slice::from_raw_parts(guid_ptr, guid_len);
+    let data = core::slice::from_raw_parts(data_ptr, data_len);
+    let dst = core::slice::from_raw_parts_mut(dst_ptr, dst_len);
+
+    let result = deferred::Decryptor::new(guid, total_size)
+        .and_then(|mut decryptor| decryptor.feed_body(data, dst));
+    match result {
+        Ok(()) => 0,
+        Err(err) => err as u32,
+    }
+}
+
+/// Second half of [`decrypt_body`], copies everything that comes from the key fragment into
+/// `dst_ptr`. Returns 0 on success or a [`decrypt::DecryptError`].
+///
+/// # Safety
+///
+/// See [`decrypt_body`], a null `key_ptr` is accepted if `key_len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn decrypt_key(
+    guid_ptr: *const u8,
+    guid_len: usize,
+    data_len: usize,
+    key_ptr: *const u8,
+    key_len: usize,
+    dst_ptr: *mut u8,
+    dst_len: usize,
+) -> u32 {
+    if guid_ptr.is_null() || dst_ptr.is_null() {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    let key = match key_slice(key_ptr, key_len) {
+        Ok(key) => key,
+        Err(err) => return err as u32,
+    };
+    let Some(total_size) = data_len.checked_add(key_len) else {
+        return decrypt::DecryptError::LengthOverflow as u32;
+    };
+    let guid = core::slice::from_raw_parts(guid_ptr, guid_len);
+    let dst = core::slice::from_raw_parts_mut(dst_ptr, dst_len);
+
+    let result = deferred::Decryptor::resume(guid, total_size, data_len)
+        .and_then(|decryptor| decryptor.feed_key(key, dst));
+    match result {
+        Ok(()) => 0,
+        Err(err) => err as u32,
+    }
+}
+
+/// Encrypt a bundle for another GUID without decrypting it into a buffer first, returns 0 on
+/// success or a [`decrypt::DecryptError`].
+///
+/// `new_data_ptr` receives `data_len + key_len - new_key_len` bytes and `new_key_ptr` the
+/// `new_key_len` bytes of the new key fragment.
+///
+/// # Safety
+///
+/// See [`try_decrypt`], a null `new_key_ptr` is accepted if `new_key_len` is 0 as well.
+#[no_mangle]
+pub unsafe extern "C" fn rekey(
+    old_guid_ptr: *const u8,
+    old_guid_len: usize,
+    new_guid_ptr: *const u8,
+    new_guid_len: usize,
+    data_ptr: *const u8,
+    data_len: usize,
+    key_ptr: *const u8,
+    key_len: usize,
+    new_data_ptr: *mut u8,
+    new_key_ptr: *mut u8,
+    new_key_len: usize,
+) -> u32 {
+    if old_guid_ptr.is_null() || new_guid_ptr.is_null() || data_ptr.is_null() {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    if new_data_ptr.is_null() {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    let key = match key_slice(key_ptr, key_len) {
+        Ok(key) => key,
+        Err(err) => return err as u32,
+    };
+    let new_key = match key_slice_mut(new_key_ptr, new_key_len) {
+        Ok(new_key) => new_key,
+        Err(err) => return err as u32,
+    };
+    let Some(total_size) = data_len.checked_add(key_len) else {
+        return decrypt::DecryptError::LengthOverflow as u32;
+    };
+    let Some(new_data_len) = total_size.checked_sub(new_key_len) else {
+        return decrypt::DecryptError::SizeMismatch as u32;
+    };
+    let old_guid = core::slice::from_raw_parts(old_guid_ptr, old_guid_len);
+    let new_guid = core::slice::from_raw_parts(new_guid_ptr, new_guid_len);
+    let data = core::slice::from_raw_parts(data_ptr, data_len);
+    let new_data = core::slice::from_raw_parts_mut(new_data_ptr, new_data_len);
+
+    match rekey::rekey(old_guid, new_guid, data, key, new_data, new_key) {
+        Ok(()) => 0,
+        Err(err) => err as u32,
+    }
+}
+
+/// Check the input for a valid UnityFS header under each scheme, returns 0 and stores the id of
+/// the first one that fits in `scheme_ptr`, otherwise a [`decrypt::DecryptError`].
+///
+/// Schemes that keep the header in place all fit, see [`scheme::probe`].
+///
+/// # Safety
+///
+/// See [`try_decrypt`].
+#[no_mangle]
+pub unsafe extern "C" fn probe_scheme(
+    guid_ptr: *const u8,
+    guid_len: usize,
+    data_ptr: *const u8,
+    data_len: usize,
+    key_ptr: *const u8,
+    key_len: usize,
+    scheme_ptr: *mut u32,
+) -> u32 {
+    if guid_ptr.is_null() || data_ptr.is_null() || scheme_ptr.is_null() {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    let key = match key_slice(key_ptr, key_len) {
+        Ok(key) => key,
+        Err(err) => return err as u32,
+    };
+    let guid = core::slice::from_raw_parts(guid_ptr, guid_len);
+    let data = core::slice::from_raw_parts(data_ptr, data_len);
+
+    match scheme::probe(guid, data, key) {
+        Ok(scheme) => {
+            *scheme_ptr = scheme as u32;
+            0
+        }
+        Err(err) => err as u32,
+    }
+}
+
+/// # Safety
+///
+/// We have to trust the caller to supply valid ptr/sz pairs to the function.
+/// `data_ptr` has to hold `src_len - key_len` bytes, `key_ptr` `key_len` bytes. Nothing is
+/// written if the input can't be encrypted, see [`try_encrypt`].
+#[no_mangle]
+pub unsafe extern "C" fn encrypt(
+    guid_ptr: *const u8,
+    guid_len: usize,
+    src_ptr: *const u8,
+    src_len: usize,
+    key_len: usize,
+    data_ptr: *mut u8,
+    key_ptr: *mut u8,
+) {
+    try_encrypt(
+        guid_ptr, guid_len, src_ptr, src_len, key_len, data_ptr, key_ptr,
+    );
+}
+
+/// Checked variant of [`encrypt`], returns 0 on success or a [`decrypt::DecryptError`].
+///
+/// A `key_len` larger than `src_len` is reported as [`decrypt::DecryptError::SizeMismatch`].
+///
+/// # Safety
+///
+/// See [`try_decrypt`].
+#[no_mangle]
+pub unsafe extern "C" fn try_encrypt(
+    guid_ptr: *const u8,
+    guid_len: usize,
+    src_ptr: *const u8,
+    src_len: usize,
+    key_len: usize,
+    data_ptr: *mut u8,
+    key_ptr: *mut u8,
+) -> u32 {
+    if guid_ptr.is_null() || src_ptr.is_null() || data_ptr.is_null() {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    let key = match key_slice_mut(key_ptr, key_len) {
+        Ok(key) => key,
+        Err(err) => return err as u32,
+    };
+    let Some(data_len) = src_len.checked_sub(key_len) else {
+        return decrypt::DecryptError::SizeMismatch as u32;
+    };
+    let guid = core::slice::from_raw_parts(guid_ptr, guid_len);
+    let src = core::slice::from_raw_parts(src_ptr, src_len);
+    let data = core::slice::from_raw_parts_mut(data_ptr, data_len);
+
+    match decrypt::try_encrypt(guid, src, data, key) {
+        Ok(()) => 0,
+        Err(err) => err as u32,
+    }
+}
+
+/// Writes the segment plan for a bundle of `total_size` bytes into `out_ptr`, returns 0 on
+/// success or a [`decrypt::DecryptError`].
+///
+/// The number of segments is always stored in `count_ptr`. If it exceeds `out_len` nothing is
+/// written and [`decrypt::DecryptError::DstTooSmall`] is returned.
+///
+/// # Safety
+///
+/// We have to trust the caller to supply valid ptr/sz pairs to the function.
+#[no_mangle]
+pub unsafe extern "C" fn segment_plan(
+    guid_ptr: *const u8,
+    guid_len: usize,
+    total_size: usize,
+    out_ptr: *mut decrypt::PlannedSegment,
+    out_len: usize,
+    count_ptr: *mut usize,
+) -> u32 {
+    if guid_ptr.is_null() || count_ptr.is_null() || (out_ptr.is_null() && out_len != 0) {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    let guid = core::slice::from_raw_parts(guid_ptr, guid_len);
+
+    let plan = match decrypt::SegmentPlan::new(guid, total_size) {
+        Ok(plan) => plan,
+        Err(err) => return err as u32,
+    };
+    *count_ptr = plan.len();
+    if plan.len() > out_len {
+        return decrypt::DecryptError::DstTooSmall as u32;
+    }
+    for (i, segment) in plan.iter().enumerate() {
+        out_ptr.add(i).write(segment);
+    }
+
+    0
+}
+
+/// Writes the parts of the segments needed for bytes `start..end` of a bundle of `total_size`
+/// bytes into `out_ptr`, returns 0 on success or a [`decrypt::DecryptError`].
+///
+/// Same as [`segment_plan`] otherwise, `src` of each entry is the range of the encrypted data
+/// (data followed by key fragment) to read for it.
+///
+/// # Safety
+///
+/// We have to trust the caller to supply valid ptr/sz pairs to the function.
+#[no_mangle]
+pub unsafe extern "C" fn segment_window(
+    guid_ptr: *const u8,
+    guid_len: usize,
+    total_size: usize,
+    start: usize,
+    end: usize,
+    out_ptr: *mut decrypt::PlannedSegment,
+    out_len: usize,
+    count_ptr: *mut usize,
+) -> u32 {
+    if guid_ptr.is_null() || count_ptr.is_null() || (out_ptr.is_null() && out_len != 0) {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    if start > end || end > total_size {
+        return decrypt::DecryptError::RangeOutOfBounds as u32;
+    }
+    let guid = core::slice::from_raw_parts(guid_ptr, guid_len);
+
+    let plan = match decrypt::SegmentPlan::new(guid, total_size) {
+        Ok(plan) => plan,
+        Err(err) => return err as u32,
+    };
+    *count_ptr = plan.window(start..end).count();
+    if *count_ptr > out_len {
+        return decrypt::DecryptError::DstTooSmall as u32;
+    }
+    for (i, segment) in plan.window(start..end).enumerate() {
+        out_ptr.add(i).write(segment);
+    }
+
+    0
+}
+
+/// Positional write callback for [`decrypt_stream`], returns 0 on success.
+#[cfg(feature = "std")]
+pub type WriteCallback = unsafe extern "C" fn(
+    ctx: *mut core::ffi::c_void,
+    offset: u64,
+    ptr: *const u8,
+    len: usize,
+) -> i32;
+
+/// Read callback for [`decrypt_stream`], returns the number of bytes read, 0 at the end of the
+/// input or a negative value on error.
+#[cfg(feature = "std")]
+pub type ReadCallback =
+    unsafe extern "C" fn(ctx: *mut core::ffi::c_void, ptr: *mut u8, len: usize) -> isize;
+
+/// Streaming variant of [`decrypt`], returns 0 on success or a [`decrypt::DecryptError`].
+///
+/// Pulls `total_size` encrypted bytes (data followed by key fragment) through `read` and hands
+/// the decrypted segments to `write` along with their offset in the bundle.
+///
+/// # Safety
+///
+/// We have to trust the caller to supply a valid ptr/sz pair and callbacks to the function.
+#[cfg(feature = "std")]
+#[no_mangle]
+pub unsafe extern "C" fn decrypt_stream(
+    guid_ptr: *const u8,
+    guid_len: usize,
+    total_size: usize,
+    read: ReadCallback,
+    read_ctx: *mut core::ffi::c_void,
+    write: WriteCallback,
+    write_ctx: *mut core::ffi::c_void,
+) -> u32 {
+    if guid_ptr.is_null() {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    let guid = core::slice::from_raw_parts(guid_ptr, guid_len);
+
+    let plan = match stream::plan(guid, total_size) {
+        Ok(plan) => plan,
+        Err(err) => return err as u32,
+    };
+
+    let reader = stream::CallbackReader {
+        read,
+        ctx: read_ctx,
+    };
+    let result = stream::copy_segments(plan.segments(), reader, |offset, buf| {
+        match write(write_ctx, offset, buf.as_ptr(), buf.len()) {
+            0 => Ok(()),
+            _ => Err(std::io::ErrorKind::Other.into()),
+        }
+    });
+    match result {
+        Ok(()) => 0,
+        Err(_) => decrypt::DecryptError::Io as u32,
+    }
+}
+
+/// Completion callback for [`decrypt_submit`], receives the job id and its status.
+#[cfg(feature = "std")]
+pub type CompletionCallback =
+    unsafe extern "C" fn(ctx: *mut core::ffi::c_void, job: u64, status: u32);
+
+/// Queue a decryption on the background worker, returns 0 on success or a
+/// [`decrypt::DecryptError`]. The job id is stored in `job_ptr`.
+///
+/// The result is either passed to `callback` on the worker thread, or has to be collected with
+/// [`decrypt_poll`] or [`decrypt_wait`]. Uncollected results count towards the queue capacity
+/// until they are collected or dropped with [`decrypt_cancel`]. A cancelled job's `callback`
+/// runs on the thread calling [`decrypt_cancel`].
+///
+/// # Safety
+///
+/// We have to trust the caller to supply valid ptr/sz pairs to the function. The buffers have to
+/// stay valid (pinned) until the job is finished.
+#[cfg(feature = "std")]
+#[no_mangle]
+pub unsafe extern "C" fn decrypt_submit(
+    guid_ptr: *const u8,
+    guid_len: usize,
+    data_ptr: *const u8,
+    data_len: usize,
+    key_ptr: *const u8,
+    key_len: usize,
+    dst_ptr: *mut u8,
+    dst_len: usize,
+    callback: Option<CompletionCallback>,
+    ctx: *mut core::ffi::c_void,
+    job_ptr: *mut u64,
+) -> u32 {
+    if guid_ptr.is_null() || data_ptr.is_null() || dst_ptr.is_null() || job_ptr.is_null() {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    if key_ptr.is_null() && key_len != 0 {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    let guid = core::slice::from_raw_parts(guid_ptr, guid_len);
+    let buffers = worker::JobBuffers {
+        data: data_ptr,
+        data_len,
+        key: key_ptr,
+        key_len,
+        dst: dst_ptr,
+        dst_len,
+    };
+
+    struct Context(*mut core::ffi::c_void);
+    unsafe impl Send for Context {}
+    let completion = callback.map(|callback| {
+        let ctx = Context(ctx);
+        Box::new(move |job, result: Result<(), decrypt::DecryptError>| {
+            let ctx = ctx;
+            callback(ctx.0, job, result.err().map_or(0, |err| err as u32))
+        }) as worker::Completion
+    });
+
+    match worker::Worker::global().submit(guid, buffers, completion) {
+        Ok(job) => {
+            *job_ptr = job;
+            0
+        }
+        Err(err) => err as u32,
+    }
+}
+
+/// Status of a job without blocking. Returns [`decrypt::DecryptError::Pending`] while it is
+/// queued or running, afterwards its result is returned once and the job is forgotten.
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn decrypt_poll(job: u64) -> u32 {
+    match worker::Worker::global().poll(job) {
+        Ok(()) => 0,
+        Err(err) => err as u32,
+    }
+}
+
+/// Block until the job is finished and return its status.
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn decrypt_wait(job: u64) -> u32 {
+    match worker::Worker::global().wait(job) {
+        Ok(()) => 0,
+        Err(err) => err as u32,
+    }
+}
+
+/// Cancel a job that hasn't started yet, returns 0 on success.
+///
+/// Its callback runs with [`decrypt::DecryptError::Cancelled`] before this returns. Running
+/// jobs report [`decrypt::DecryptError::Pending`], finished jobs are forgotten along with their
+/// result.
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn decrypt_cancel(job: u64) -> u32 {
+    match worker::Worker::global().cancel(job) {
+        Ok(()) => 0,
+        Err(err) => err as u32,
+    }
+}
+
+/// Multi-threaded variant of [`try_decrypt`], returns 0 on success or a
+/// [`decrypt::DecryptError`].
+///
+/// `threads` of 0 uses all available cores. Bundles smaller than `threshold` bytes are
+/// decrypted on the calling thread, pass [`parallel::PARALLEL_THRESHOLD`] if unsure.
+///
+/// # Safety
+///
+/// See [`try_decrypt`].
+#[cfg(feature = "std")]
+#[no_mangle]
+pub unsafe extern "C" fn try_decrypt_parallel(
+    guid_ptr: *const u8,
+    guid_len: usize,
+    data_ptr: *const u8,
+    data_len: usize,
+    key_ptr: *const u8,
+    key_len: usize,
+    dst_ptr: *mut u8,
+    dst_len: usize,
+    threads: usize,
+    threshold: usize,
+) -> u32 {
+    if guid_ptr.is_null() || data_ptr.is_null() || dst_ptr.is_null() {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    let key = match key_slice(key_ptr, key_len) {
+        Ok(key) => key,
+        Err(err) => return err as u32,
+    };
+    let guid = core::slice::from_raw_parts(guid_ptr, guid_len);
+    let data = core::slice::from_raw_parts(data_ptr, data_len);
+    let dst = core::slice::from_raw_parts_mut(dst_ptr, dst_len);
+
+    match parallel::decrypt_parallel(guid, data, key, dst, threads, threshold) {
+        Ok(()) => 0,
+        Err(err) => err as u32,
+    }
+}
+
+/// Decrypts `count` bundles in one call, returns 0 once all were processed or
+/// [`decrypt::DecryptError::NullPointer`].
+///
+/// The status of every item is written to `statuses_ptr`, 0 for success or a
+/// [`decrypt::DecryptError`], and the time it took to its `nanos`. The items are spread
+/// across `threads` threads, 0 uses all available cores.
+///
+/// # Safety
+///
+/// `items_ptr` and `statuses_ptr` have to hold `count` entries. Pointers in the items are
+/// checked for null, see [`try_decrypt_parallel`] for the rest.
+#[cfg(feature = "std")]
+#[no_mangle]
+pub unsafe extern "C" fn decrypt_batch(
+    items_ptr: *mut batch::BatchItem,
+    count: usize,
+    statuses_ptr: *mut u32,
+    threads: usize,
+) -> u32 {
+    if count > 0 && (items_ptr.is_null() || statuses_ptr.is_null()) {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    if count == 0 {
+        return 0;
+    }
+    let descriptors = core::slice::from_raw_parts_mut(items_ptr, count);
+    let statuses = core::slice::from_raw_parts_mut(statuses_ptr, count);
+
+    for descriptor in descriptors.iter_mut() {
+        descriptor.nanos = 0;
+    }
+    // Invalid descriptors are reported right away, the rest goes into the batch
+    let mut items = Vec::with_capacity(count);
+    let mut indices = Vec::with_capacity(count);
+    for (index, descriptor) in descriptors.iter().enumerate() {
+        match descriptor.item() {
+            Ok(item) => {
+                items.push(item);
+                indices.push(index);
+            }
+            Err(err) => statuses[index] = err as u32,
+        }
+    }
+    let outcomes = batch::decrypt_batch(&mut items, threads);
+    drop(items);
+
+    for (index, outcome) in indices.into_iter().zip(outcomes) {
+        statuses[index] = match outcome.result {
+            Ok(()) => 0,
+            Err(err) => err as u32,
+        };
+        descriptors[index].nanos = outcome.elapsed.as_nanos() as u64;
+    }
+
+    0
+}
+
+/// Use `dir` for [`cache_lookup`] and [`cache_store`], keeping at most `max_size` bytes of
+/// decrypted bundles. Returns 0 on success or a [`decrypt::DecryptError`].
+///
+/// # Safety
+///
+/// We have to trust the caller to supply a valid ptr/sz pair holding a UTF-8 path.
+#[cfg(feature = "std")]
+#[no_mangle]
+pub unsafe extern "C" fn cache_open(dir_ptr: *const u8, dir_len: usize, max_size: u64) -> u32 {
+    if dir_ptr.is_null() {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    let Ok(dir) = core::str::from_utf8(core::slice::from_raw_parts(dir_ptr, dir_len)) else {
+        return decrypt::DecryptError::Io as u32;
+    };
+
+    match cache::Cache::open(dir, max_size) {
+        Ok(cache) => {
+            *cache::Cache::global().write().unwrap() = Some(cache);
+            0
+        }
+        Err(_) => decrypt::DecryptError::Io as u32,
+    }
+}
+
+/// Copy the cached decryption of the input into `dst`, returns 0 on a hit,
+/// [`decrypt::DecryptError::CacheMiss`] if there is none or another [`decrypt::DecryptError`].
+///
+/// # Safety
+///
+/// See [`try_decrypt`].
+#[cfg(feature = "std")]
+#[no_mangle]
+pub unsafe extern "C" fn cache_lookup(
+    guid_ptr: *const u8,
+    guid_len: usize,
+    data_ptr: *const u8,
+    data_len: usize,
+    key_ptr: *const u8,
+    key_len: usize,
+    dst_ptr: *mut u8,
+    dst_len: usize,
+) -> u32 {
+    if guid_ptr.is_null() || data_ptr.is_null() || dst_ptr.is_null() {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    let key = match key_slice(key_ptr, key_len) {
+        Ok(key) => key,
+        Err(err) => return err as u32,
+    };
+    let guid = core::slice::from_raw_parts(guid_ptr, guid_len);
+    let data = core::slice::from_raw_parts(data_ptr, data_len);
+    let dst = core::slice::from_raw_parts_mut(dst_ptr, dst_len);
+
+    let Some(total_size) = data_len.checked_add(key_len) else {
+        return decrypt::DecryptError::LengthOverflow as u32;
+    };
+    let Some(dst) = dst.get_mut(..total_size) else {
+        return decrypt::DecryptError::DstTooSmall as u32;
+    };
+    let cache = cache::Cache::global().read().unwrap();
+    let Some(cache) = cache.as_ref() else {
+        return decrypt::DecryptError::NoCache as u32;
+    };
+    match cache.get(&cache::CacheKey::new(guid, data, key), dst) {
+        Ok(true) => 0,
+        Ok(false) => decrypt::DecryptError::CacheMiss as u32,
+        Err(_) => decrypt::DecryptError::Io as u32,
+    }
+}
+
+/// Store the decryption `dec_ptr` of the input, returns 0 on success or a
+/// [`decrypt::DecryptError`].
+///
+/// # Safety
+///
+/// See [`try_decrypt`].
+#[cfg(feature = "std")]
+#[no_mangle]
+pub unsafe extern "C" fn cache_store(
+    guid_ptr: *const u8,
+    guid_len: usize,
+    data_ptr: *const u8,
+    data_len: usize,
+    key_ptr: *const u8,
+    key_len: usize,
+    dec_ptr: *const u8,
+    dec_len: usize,
+) -> u32 {
+    if guid_ptr.is_null() || data_ptr.is_null() || dec_ptr.is_null() {
+        return decrypt::DecryptError::NullPointer as u32;
+    }
+    let key = match key_slice(key_ptr, key_len) {
+        Ok(key) => key,
+        Err(err) => return err as u32,
+    };
+    let guid = core::slice::from_raw_parts(guid_ptr, guid_len);
+    let data = core::slice::from_raw_parts(data_ptr, data_len);
+    let dec = core::slice::from_raw_parts(dec_ptr, dec_len);
+
+    if data_len.checked_add(key_len) != Some(dec_len) {
+        return decrypt::DecryptError::SizeMismatch as u32;
+    }
+    let cache = cache::Cache::global().read().unwrap();
+    let Some(cache) = cache.as_ref() else {
+        return decrypt::DecryptError::NoCache as u32;
+    };
+    match cache.put(&cache::CacheKey::new(guid, data, key), dec) {
+        Ok(()) => 0,
+        Err(_) => decrypt::DecryptError::Io as u32,
+    }
 }
 
-mod decrypt;
+pub mod api;
+#[cfg(feature = "std")]
+pub mod batch;
+#[cfg(feature = "std")]
+pub mod cache;
+#[cfg(feature = "std")]
+pub mod decompress;
+pub mod decrypt;
+pub mod deferred;
+#[cfg(feature = "std")]
+pub mod identify;
+pub mod lz4;
+#[cfg(any(test, feature = "boehm"))]
+mod mono;
+#[cfg(feature = "std")]
+pub mod parallel;
+pub mod rekey;
+#[cfg(test)]
+mod sample;
+pub mod scheme;
+#[cfg(feature = "std")]
+pub mod serialized;
+#[cfg(feature = "std")]
+pub mod stream;
+pub mod unityfs;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "std")]
+pub mod worker;
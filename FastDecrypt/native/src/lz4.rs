@@ -0,0 +1,98 @@
+/// Decompress a raw LZ4 block (no frame) into `dst`, returns the number of bytes written.
+///
+/// LZ4HC produces the same block format, so this covers both compression types Unity uses.
+pub fn decompress(src: &[u8], dst: &mut [u8]) -> Option<usize> {
+    let mut i = 0;
+    let mut o = 0;
+    loop {
+        let token = *src.get(i)?;
+        i += 1;
+
+        // Literals
+        let len = length(src, &mut i, token >> 4)?;
+        let literals = src.get(i..i.checked_add(len)?)?;
+        dst.get_mut(o..o + len)?.copy_from_slice(literals);
+        i += len;
+        o += len;
+
+        // The last sequence only has literals
+        if i == src.len() {
+            return Some(o);
+        }
+
+        // Match
+        let offset = u16::from_le_bytes([*src.get(i)?, *src.get(i + 1)?]) as usize;
+        i += 2;
+        if offset == 0 || offset > o {
+            return None;
+        }
+        let len = length(src, &mut i, token & 0xf)?.checked_add(4)?;
+        if o.checked_add(len)? > dst.len() {
+            return None;
+        }
+        // Matches may overlap their own output
+        for _ in 0..len {
+            dst[o] = dst[o - offset];
+            o += 1;
+        }
+    }
+}
+
+/// Read the rest of a length that starts in a token nibble.
+fn length(src: &[u8], i: &mut usize, nibble: u8) -> Option<usize> {
+    let mut len = nibble as usize;
+    if nibble == 0xf {
+        loop {
+            let byte = *src.get(*i)?;
+            *i += 1;
+            len = len.checked_add(byte as usize)?;
+            if byte != 0xff {
+                break;
+            }
+        }
+    }
+    Some(len)
+}
+
+/// Encode `data` as a single literal run, which is valid, if useless, LZ4.
+#[cfg(test)]
+pub(crate) fn compress_literals(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    match data.len() {
+        len if len < 15 => out.push((len as u8) << 4),
+        len => {
+            out.push(0xf0);
+            let mut rest = len - 15;
+            while rest >= 255 {
+                out.push(0xff);
+                rest -= 255;
+            }
+            out.push(rest as u8);
+        }
+    }
+    out.extend_from_slice(data);
+    out
+}
+
+#[test]
+fn test_decompress() {
+    // "abc", then a match 3 back for 9 bytes, then "xyzzy"
+    let src = [
+        0x35, b'a', b'b', b'c', 0x03, 0x00, 0x50, b'x', b'y', b'z', b'z', b'y',
+    ];
+    let mut dst = [0; 17];
+    assert_eq!(decompress(&src, &mut dst), Some(17));
+    assert_eq!(&dst, b"abcabcabcabcxyzzy");
+    assert_eq!(decompress(&src, &mut dst[..16]), None);
+
+    let data: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+    let mut dst = vec![0; 1000];
+    assert_eq!(decompress(&compress_literals(&data), &mut dst), Some(1000));
+    assert_eq!(dst, data);
+
+    // Offset pointing in front of the output
+    assert_eq!(
+        decompress(&[0x10, b'a', 0x02, 0x00, 0x00], &mut [0; 8]),
+        None
+    );
+}
@@ -1,44 +1,516 @@
-mod decrypt;
-
-fn main() {
-    use std::collections::hash_map::DefaultHasher;
-    use std::fs;
-    use std::hash::{Hash, Hasher};
-    use std::time::Instant;
-
-    let guids = &[
-        "2c99f767-53b9-463c-aa99-791b04cd9003",
-        "8611ee9e-0c57-48d2-af32-7f980b0895db",
-        "6586c486-4731-4fae-a2d2-de415cd8bcd6",
-        "6b86cced-e17c-4f57-8bdf-812615773ce6",
-        "32ceb35d-24fa-469f-8aa4-23851ac68f84",
-        "17c267db-18c4-4900-bb73-ad323f082640",
-        "5dc14ba2-7164-40e9-8b52-f55cf3129a24",
-        "67e08c5c-d918-478e-ad8d-58e884fa53b4",
-        "9d1d8585-9c0b-40d9-8721-76f21cc745f2",
-    ];
-    for guid in guids {
-        let enc = fs::read(format!("tests/{guid}.enc")).unwrap();
-        let key = fs::read(format!("tests/{guid}.key")).unwrap();
-        let mut dec = Vec::<u8>::new();
-        dec.resize(enc.len() + key.len(), 0x42);
-        let now = Instant::now();
-        crate::decrypt::decrypt_internal(guid.as_bytes(), &enc, &key, &mut dec);
-        let elapsed = now.elapsed();
-        println!(
-            "guid: {guid}, elapsed: {elapsed:?}, {} MiB/s",
-            dec.len() as f32 / 1024.0 / 1024.0 / elapsed.as_secs_f32()
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::Instant;
+
+use libdec::decrypt::{try_decrypt, try_encrypt, SegmentPlan};
+use libdec::identify::identify;
+use libdec::parallel::{decrypt_parallel, PARALLEL_THRESHOLD};
+use libdec::rekey::rekey;
+use libdec::unityfs;
+use xxhash_rust::xxh3::xxh3_128;
+
+const USAGE: &str = "\
+usage: cvrdec <command> [options]
+
+commands:
+  decrypt [--guid G] <in.enc> [in.key] -o <out.bundle> [--threads N] [--force]
+          [--expect HASH]
+      Decrypt a bundle and print its XXH128 like `xxhsum -H2`. Refuses to write
+      output that isn't a valid UnityFS bundle unless --force is given, or that
+      doesn't match the expected hash.
+  encrypt --guid G <in.bundle> -o <out.enc> [--key-len N --key-out <out.key>]
+      Encrypt a bundle, optionally splitting off the last N bytes as key fragment.
+  rekey [--guid G] <in.enc> [in.key] --new-guid N -o <out.enc>
+        [--key-len N --key-out <out.key>]
+      Encrypt a bundle for another GUID without writing it out decrypted.
+  verify [--guid G] <in.enc> [in.key] <expected.dec>
+      Decrypt and compare against an already decrypted bundle.
+  bench [dir]
+      Time decryption of every <guid>.enc (with <guid>.key and <guid>.dec if present)
+      in dir, defaults to tests.
+  identify <in.enc> [in.key] (--guid G... | --candidates <file>)
+      Find which of the candidate GUIDs, given with --guid or one per line in a
+      file, the input was encrypted with and print it.
+  plan --guid G <size | file...>
+      Print the segments a bundle of the given size, or of the files' total size, is
+      cut into.
+
+The GUID defaults to the file name of the encrypted input without extension.
+Exit codes: 0 success, 1 failure, 2 invalid usage.
+";
+
+/// Why a command failed, decides the exit code.
+enum Failure {
+    Usage(String),
+    Failed(String),
+}
+
+fn usage<T>(message: impl Into<String>) -> Result<T, Failure> {
+    Err(Failure::Usage(message.into()))
+}
+
+fn failed<T>(message: impl Into<String>) -> Result<T, Failure> {
+    Err(Failure::Failed(message.into()))
+}
+
+/// Options taking a value and flags without one.
+const OPTIONS: &[&str] = &[
+    "--guid",
+    "-o",
+    "--threads",
+    "--key-len",
+    "--key-out",
+    "--expect",
+    "--candidates",
+    "--new-guid",
+];
+const FLAGS: &[&str] = &["--force"];
+
+#[derive(Default)]
+struct Args {
+    positional: Vec<String>,
+    options: Vec<(&'static str, String)>,
+    flags: Vec<&'static str>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, Failure> {
+        let mut parsed = Self::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if let Some(&name) = OPTIONS.iter().find(|&&name| name == arg) {
+                let Some(value) = args.next() else {
+                    return usage(format!("{name} needs a value"));
+                };
+                parsed.options.push((name, value.clone()));
+            } else if let Some(&name) = FLAGS.iter().find(|&&name| name == arg) {
+                parsed.flags.push(name);
+            } else if arg.starts_with('-') && arg.len() > 1 {
+                return usage(format!("unknown option {arg}"));
+            } else {
+                parsed.positional.push(arg.clone());
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(option, _)| *option == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn number(&self, name: &str, default: usize) -> Result<usize, Failure> {
+        match self.option(name) {
+            None => Ok(default),
+            Some(value) => match value.parse() {
+                Ok(number) => Ok(number),
+                Err(_) => usage(format!("{name} expects a number, got {value}")),
+            },
+        }
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.contains(&name)
+    }
+
+    /// `--guid`, or the file name of the encrypted input.
+    fn guid(&self, input: &str) -> Result<String, Failure> {
+        if let Some(guid) = self.option("--guid") {
+            return Ok(guid.to_owned());
+        }
+        match Path::new(input).file_stem().and_then(|stem| stem.to_str()) {
+            Some(stem) if !stem.is_empty() => Ok(stem.to_owned()),
+            _ => usage(format!("can't derive a GUID from {input}, pass --guid")),
+        }
+    }
+
+    fn output(&self) -> Result<&str, Failure> {
+        match self.option("-o") {
+            Some(path) => Ok(path),
+            None => usage("missing -o <output>"),
+        }
+    }
+}
+
+fn read(path: &str) -> Result<Vec<u8>, Failure> {
+    fs::read(path).or_else(|err| failed(format!("can't read {path}: {err}")))
+}
+
+fn write(path: &str, data: &[u8]) -> Result<(), Failure> {
+    fs::write(path, data).or_else(|err| failed(format!("can't write {path}: {err}")))
+}
+
+fn decrypt_files(
+    guid: &str,
+    enc_path: &str,
+    key_path: Option<&str>,
+    threads: usize,
+) -> Result<Vec<u8>, Failure> {
+    let enc = read(enc_path)?;
+    let key = match key_path {
+        Some(path) => read(path)?,
+        None => Vec::new(),
+    };
+    let mut dec = vec![0; enc.len() + key.len()];
+    match decrypt_parallel(
+        guid.as_bytes(),
+        &enc,
+        &key,
+        &mut dec,
+        threads,
+        PARALLEL_THRESHOLD,
+    ) {
+        Ok(()) => Ok(dec),
+        Err(err) => failed(format!("decrypting {enc_path} failed: {err}")),
+    }
+}
+
+fn decrypt_command(args: &Args) -> Result<(), Failure> {
+    let (enc_path, key_path) = match args.positional.as_slice() {
+        [enc] => (enc, None),
+        [enc, key] => (enc, Some(key.as_str())),
+        _ => return usage("decrypt takes the encrypted file and an optional key fragment"),
+    };
+    let output = args.output()?;
+    let guid = args.guid(enc_path)?;
+    let expected = match args.option("--expect") {
+        None => None,
+        Some(hash) => match u128::from_str_radix(hash, 16) {
+            Ok(value) if hash.len() == 32 => Some(value),
+            _ => return usage(format!("--expect takes 32 hex digits, got {hash}")),
+        },
+    };
+    let dec = decrypt_files(&guid, enc_path, key_path, args.number("--threads", 0)?)?;
+    if !args.flag("--force") {
+        if let Err(err) = unityfs::validate(&dec) {
+            return failed(format!(
+                "{enc_path} decrypted with GUID {guid}: {err}, truncated or corrupt input"
+            ));
+        }
+    }
+    let hash = xxh3_128(&dec);
+    if expected.is_some_and(|expected| expected != hash) {
+        return failed(format!(
+            "{enc_path}: hash {hash:032x} isn't the expected one"
+        ));
+    }
+    write(output, &dec)?;
+    println!("{hash:032x}  {output}");
+    Ok(())
+}
+
+fn encrypt_command(args: &Args) -> Result<(), Failure> {
+    let [input] = args.positional.as_slice() else {
+        return usage("encrypt takes exactly one bundle");
+    };
+    let Some(guid) = args.option("--guid") else {
+        return usage("encrypt needs --guid");
+    };
+    let output = args.output()?;
+    let key_len = args.number("--key-len", 0)?;
+    let key_out = args.option("--key-out");
+    if key_len > 0 && key_out.is_none() {
+        return usage("--key-len needs --key-out");
+    }
+
+    let src = read(input)?;
+    if src.is_empty() || key_len >= src.len() {
+        return failed(format!(
+            "{input} has {} bytes, can't split off {key_len} key bytes",
+            src.len()
+        ));
+    }
+    let mut bytes = vec![0; src.len() - key_len];
+    let mut key = vec![0; key_len];
+    if let Err(err) = try_encrypt(guid.as_bytes(), &src, &mut bytes, &mut key) {
+        return failed(format!("can't encrypt {input} with GUID {guid}: {err}"));
+    }
+
+    write(output, &bytes)?;
+    match key_out {
+        Some(path) => write(path, &key),
+        None => Ok(()),
+    }
+}
+
+fn verify_command(args: &Args) -> Result<(), Failure> {
+    let (enc_path, key_path, dec_path) = match args.positional.as_slice() {
+        [enc, dec] => (enc, None, dec),
+        [enc, key, dec] => (enc, Some(key.as_str()), dec),
+        _ => return usage("verify takes the encrypted file, optional key and decrypted file"),
+    };
+    let guid = args.guid(enc_path)?;
+    let dec = decrypt_files(&guid, enc_path, key_path, args.number("--threads", 0)?)?;
+    let want = read(dec_path)?;
+    compare(&dec, &want, dec_path)?;
+    println!("{enc_path}: ok");
+    Ok(())
+}
+
+fn compare(got: &[u8], want: &[u8], name: &str) -> Result<(), Failure> {
+    if let Some(offset) = got.iter().zip(want).position(|(a, b)| a != b) {
+        return failed(format!("differs from {name} at offset {offset}"));
+    }
+    if got.len() != want.len() {
+        return failed(format!(
+            "{} bytes, but {name} has {} bytes",
+            got.len(),
+            want.len()
+        ));
+    }
+    Ok(())
+}
+
+fn bench_command(args: &Args) -> Result<(), Failure> {
+    let dir = match args.positional.as_slice() {
+        [] => "tests",
+        [dir] => dir.as_str(),
+        _ => return usage("bench takes at most one directory"),
+    };
+    let entries = fs::read_dir(dir).or_else(|err| failed(format!("can't list {dir}: {err}")))?;
+    let mut guids: Vec<String> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            match path.extension()?.to_str()? {
+                "enc" => Some(path.file_stem()?.to_str()?.to_owned()),
+                _ => None,
+            }
+        })
+        .collect();
+    guids.sort();
+    if guids.is_empty() {
+        return failed(format!("no .enc files in {dir}"));
+    }
+
+    let mut failures = 0;
+    for guid in &guids {
+        if let Err(Failure::Failed(message) | Failure::Usage(message)) = bench(dir, guid) {
+            eprintln!("cvrdec: {guid}: {message}");
+            failures += 1;
+        }
+    }
+    match failures {
+        0 => Ok(()),
+        n => failed(format!("{n} of {} bundles failed", guids.len())),
+    }
+}
+
+fn bench(dir: &str, guid: &str) -> Result<(), Failure> {
+    let path = |extension: &str| format!("{dir}/{guid}.{extension}");
+    let enc = read(&path("enc"))?;
+    let key = match Path::new(&path("key")).exists() {
+        true => read(&path("key"))?,
+        false => Vec::new(),
+    };
+    let mib_per_s = |len: usize, secs: f32| len as f32 / 1024.0 / 1024.0 / secs;
+
+    let mut dec = vec![0x42; enc.len() + key.len()];
+    let now = Instant::now();
+    if let Err(err) = try_decrypt(guid.as_bytes(), &enc, &key, &mut dec) {
+        return failed(format!("decryption failed: {err}"));
+    }
+    let elapsed = now.elapsed();
+    println!(
+        "guid: {guid}, elapsed: {elapsed:?}, {} MiB/s",
+        mib_per_s(dec.len(), elapsed.as_secs_f32())
+    );
+
+    let mut par = vec![0x42; dec.len()];
+    let now = Instant::now();
+    if let Err(err) = decrypt_parallel(guid.as_bytes(), &enc, &key, &mut par, 0, 0) {
+        return failed(format!("parallel decryption failed: {err}"));
+    }
+    let elapsed = now.elapsed();
+    println!(
+        "guid: {guid}, parallel elapsed: {elapsed:?}, {} MiB/s",
+        mib_per_s(par.len(), elapsed.as_secs_f32())
+    );
+    if par != dec {
+        return failed("parallel decryption differs");
+    }
+
+    if Path::new(&path("dec")).exists() {
+        compare(&dec, &read(&path("dec"))?, &path("dec"))?;
+    }
+
+    let mut data = vec![0; enc.len()];
+    let mut frag = vec![0; key.len()];
+    let encrypted = try_encrypt(guid.as_bytes(), &dec, &mut data, &mut frag);
+    if encrypted.is_err() || data != enc || frag != key {
+        return failed("encrypting doesn't reproduce the input");
+    }
+    Ok(())
+}
+
+fn rekey_command(args: &Args) -> Result<(), Failure> {
+    let (enc_path, key_path) = match args.positional.as_slice() {
+        [enc] => (enc, None),
+        [enc, key] => (enc, Some(key)),
+        _ => return usage("rekey takes the encrypted file and an optional key fragment"),
+    };
+    let guid = args.guid(enc_path)?;
+    let Some(new_guid) = args.option("--new-guid") else {
+        return usage("rekey needs --new-guid");
+    };
+    let output = args.output()?;
+    let key_len = args.number("--key-len", 0)?;
+    let key_out = args.option("--key-out");
+    if key_len > 0 && key_out.is_none() {
+        return usage("--key-len needs --key-out");
+    }
+
+    let enc = read(enc_path)?;
+    let key = match key_path {
+        Some(path) => read(path)?,
+        None => Vec::new(),
+    };
+    let total_size = enc.len() + key.len();
+    if key_len >= total_size {
+        return failed(format!(
+            "{enc_path} has {total_size} bytes, can't split off {key_len} key bytes"
+        ));
+    }
+    let mut new_bytes = vec![0; total_size - key_len];
+    let mut new_key = vec![0; key_len];
+    if let Err(err) = rekey(
+        guid.as_bytes(),
+        new_guid.as_bytes(),
+        &enc,
+        &key,
+        &mut new_bytes,
+        &mut new_key,
+    ) {
+        return failed(format!("can't rekey {enc_path} to GUID {new_guid}: {err}"));
+    }
+
+    write(output, &new_bytes)?;
+    match key_out {
+        Some(path) => write(path, &new_key),
+        None => Ok(()),
+    }
+}
+
+fn identify_command(args: &Args) -> Result<(), Failure> {
+    let (enc_path, key_path) = match args.positional.as_slice() {
+        [enc] => (enc, None),
+        [enc, key] => (enc, Some(key)),
+        _ => return usage("identify takes the encrypted file and an optional key fragment"),
+    };
+    let mut candidates: Vec<String> = args
+        .options
+        .iter()
+        .filter(|(name, _)| *name == "--guid")
+        .map(|(_, guid)| guid.clone())
+        .collect();
+    if let Some(path) = args.option("--candidates") {
+        let list =
+            fs::read_to_string(path).or_else(|err| failed(format!("can't read {path}: {err}")))?;
+        candidates.extend(
+            list.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_owned),
         );
+    }
+    if candidates.is_empty() {
+        return usage("identify needs --guid or --candidates");
+    }
+
+    let enc = read(enc_path)?;
+    let key = match key_path {
+        Some(path) => read(path)?,
+        None => Vec::new(),
+    };
+    match identify(&candidates, &enc, &key) {
+        Ok(Some(index)) => {
+            println!("{}", candidates[index]);
+            Ok(())
+        }
+        Ok(None) => failed(format!(
+            "none of the {} candidates decrypts {enc_path}",
+            candidates.len()
+        )),
+        Err(err) => failed(format!("can't identify {enc_path}: {err}")),
+    }
+}
 
-        let mut hasher = DefaultHasher::new();
-        dec.hash(&mut hasher);
-        let got = hasher.finish();
+fn plan_command(args: &Args) -> Result<(), Failure> {
+    let Some(guid) = args.option("--guid") else {
+        return usage("plan needs --guid");
+    };
+    let total_size = match args.positional.as_slice() {
+        [] => return usage("plan takes a size or the encrypted files"),
+        [size] if size.parse::<usize>().is_ok() => size.parse().unwrap(),
+        files => {
+            let mut total = 0;
+            for file in files {
+                let metadata = fs::metadata(file)
+                    .or_else(|err| failed(format!("can't stat {file}: {err}")))?;
+                total += metadata.len() as usize;
+            }
+            total
+        }
+    };
 
-        let mut hasher = DefaultHasher::new();
-        let want = fs::read(format!("tests/{guid}.dec")).unwrap();
-        want.hash(&mut hasher);
-        let expected = hasher.finish();
+    let plan = match SegmentPlan::new(guid.as_bytes(), total_size) {
+        Ok(plan) => plan,
+        Err(err) => return failed(format!("no plan for {total_size} bytes: {err}")),
+    };
+    // Written by hand, so a closed pipe ends the listing instead of panicking
+    let mut out = std::io::stdout().lock();
+    let mut table = || -> std::io::Result<()> {
+        writeln!(
+            out,
+            "{:>7} {:>12} {:>12} {:>12}",
+            "segment", "src", "dst", "len"
+        )?;
+        for (index, segment) in plan.iter().enumerate() {
+            writeln!(
+                out,
+                "{index:>7} {:>12} {:>12} {:>12}",
+                segment.src, segment.dst, segment.len
+            )?;
+        }
+        out.flush()
+    };
+    table().or_else(|err| failed(format!("can't write the plan: {err}")))
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some((command, rest)) = args.split_first() else {
+        eprint!("{USAGE}");
+        return ExitCode::from(2);
+    };
+    if matches!(command.as_str(), "-h" | "--help" | "help") {
+        print!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
 
-        assert_eq!(got, expected);
+    let run: fn(&Args) -> Result<(), Failure> = match command.as_str() {
+        "decrypt" => decrypt_command,
+        "encrypt" => encrypt_command,
+        "verify" => verify_command,
+        "bench" => bench_command,
+        "rekey" => rekey_command,
+        "identify" => identify_command,
+        "plan" => plan_command,
+        _ => |_: &Args| usage("unknown command"),
+    };
+    match Args::parse(rest).and_then(|args| run(&args)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Failure::Usage(message)) => {
+            eprint!("cvrdec: {command}: {message}\n\n{USAGE}");
+            ExitCode::from(2)
+        }
+        Err(Failure::Failed(message)) => {
+            eprintln!("cvrdec: {command}: {message}");
+            ExitCode::from(1)
+        }
     }
 }
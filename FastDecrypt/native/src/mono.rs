@@ -0,0 +1,160 @@
+// Only the DLL entry point of the boehm build calls into here, tests check the layout
+#![allow(dead_code)]
+
+use core::ffi::c_void;
+
+#[cfg(feature = "boehm")]
+use crate::decrypt::{compute_crc_utf16, reassemble, DecryptError, SegmentPlan};
+#[cfg(feature = "boehm")]
+use crate::scheme::Scheme;
+
+#[cfg(feature = "boehm")]
+extern "C" {
+    fn mono_add_internal_call(name: *const core::ffi::c_char, method: *const c_void);
+    fn mono_domain_get() -> *mut MonoDomain;
+    fn mono_get_byte_class() -> *mut MonoClass;
+    fn mono_array_new(domain: *mut MonoDomain, eclass: *mut MonoClass, n: usize) -> *mut MonoArray;
+}
+
+#[repr(C)]
+pub struct MonoDomain {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct MonoClass {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct MonoObject {
+    vtable: *const c_void,
+    sync: *const c_void,
+}
+
+#[repr(C)]
+pub struct MonoString {
+    object: MonoObject,
+    length: i32,
+    chars: [u16; 0],
+}
+
+impl MonoString {
+    pub unsafe fn chars(&self) -> &[u16] {
+        core::slice::from_raw_parts(self.chars.as_ptr(), self.length as usize)
+    }
+}
+
+#[repr(C)]
+pub struct MonoArray {
+    object: MonoObject,
+    bounds: *const c_void,
+    max_length: usize,
+    /// Mono aligns the elements to 8 bytes
+    vector: [u64; 0],
+}
+
+impl MonoArray {
+    /// Elements of a `byte[]`.
+    pub unsafe fn bytes(&self) -> &[u8] {
+        core::slice::from_raw_parts(self.vector.as_ptr().cast(), self.max_length)
+    }
+
+    /// Elements of a `byte[]`.
+    pub unsafe fn bytes_mut(&mut self) -> &mut [u8] {
+        core::slice::from_raw_parts_mut(self.vector.as_mut_ptr().cast(), self.max_length)
+    }
+}
+
+/// Register the internal calls, done by the DLL entry point when the mod loads the library.
+#[cfg(feature = "boehm")]
+pub unsafe fn register() {
+    mono_add_internal_call(
+        c"FastDecrypt.Starter::Decrypt".as_ptr(),
+        decrypt_icall as *const c_void,
+    );
+}
+
+/// `static extern byte[] Decrypt(string guid, byte[] bytes, byte[] keyFrag, out uint status)`
+///
+/// Returns the decrypted bundle in a new array, or null with `status` set to a
+/// [`DecryptError`]. The bundle has to pass the same checks as `try_decrypt_validated`.
+///
+/// The arguments don't need pinning, Mono keeps objects referenced from native frames alive
+/// and in place.
+#[cfg(feature = "boehm")]
+unsafe extern "C" fn decrypt_icall(
+    guid: *const MonoString,
+    bytes: *const MonoArray,
+    key_frag: *const MonoArray,
+    status: *mut u32,
+) -> *mut MonoArray {
+    let (array, result) = match decrypt_array(guid, bytes, key_frag) {
+        Ok(array) => (array, 0),
+        Err(err) => (core::ptr::null_mut(), err as u32),
+    };
+    if let Some(status) = status.as_mut() {
+        *status = result;
+    }
+    array
+}
+
+#[cfg(feature = "boehm")]
+unsafe fn decrypt_array(
+    guid: *const MonoString,
+    bytes: *const MonoArray,
+    key_frag: *const MonoArray,
+) -> Result<*mut MonoArray, DecryptError> {
+    let (Some(guid), Some(bytes), Some(key_frag)) =
+        (guid.as_ref(), bytes.as_ref(), key_frag.as_ref())
+    else {
+        return Err(DecryptError::NullPointer);
+    };
+    let (guid, bytes, key_frag) = (guid.chars(), bytes.bytes(), key_frag.bytes());
+    if guid.is_empty() || bytes.is_empty() {
+        return Err(DecryptError::ZeroLength);
+    }
+    let total_size = bytes
+        .len()
+        .checked_add(key_frag.len())
+        .ok_or(DecryptError::LengthOverflow)?;
+
+    let crc = compute_crc_utf16(guid).ok_or(DecryptError::NonAsciiGuid)?;
+    let plan = SegmentPlan::from_crc(Scheme::V1, crc, total_size)?;
+
+    let array = mono_array_new(mono_domain_get(), mono_get_byte_class(), total_size);
+    let dst = array.as_mut().ok_or(DecryptError::NullPointer)?.bytes_mut();
+    reassemble(plan.segments(), bytes, key_frag, dst);
+    crate::unityfs::validate(dst)?;
+
+    Ok(array)
+}
+
+#[test]
+fn test_layout() {
+    use core::mem::{offset_of, size_of};
+
+    if cfg!(target_pointer_width = "64") {
+        assert_eq!(size_of::<MonoObject>(), 0x10);
+        assert_eq!(offset_of!(MonoString, chars), 0x14);
+        assert_eq!(offset_of!(MonoArray, max_length), 0x18);
+        assert_eq!(offset_of!(MonoArray, vector), 0x20);
+    }
+
+    // A string and a byte[] as Mono lays them out
+    let mut string = vec![0u64; 16];
+    let guid: Vec<u16> = "9d1d8585".encode_utf16().collect();
+    unsafe {
+        let object = string.as_mut_ptr().cast::<MonoString>();
+        (*object).length = guid.len() as i32;
+        core::ptr::copy_nonoverlapping(guid.as_ptr(), (*object).chars.as_mut_ptr(), guid.len());
+        assert_eq!((*object).chars(), guid);
+    }
+    let mut array = vec![0u64; 16];
+    unsafe {
+        let object = array.as_mut_ptr().cast::<MonoArray>();
+        (*object).max_length = 10;
+        (*object).bytes_mut().copy_from_slice(b"UnityFS\0\0\0");
+        assert_eq!((*object).bytes(), b"UnityFS\0\0\0");
+    }
+}
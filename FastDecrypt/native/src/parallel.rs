@@ -0,0 +1,100 @@
+use crate::decrypt::{check_input, copy_from, try_decrypt, DecryptError, SegmentPlan};
+
+/// Bundles smaller than this are decrypted on the calling thread, spawning threads costs more
+/// than it saves there.
+pub const PARALLEL_THRESHOLD: usize = 16 * 1024 * 1024;
+
+/// `threads`, or the available parallelism if it's 0.
+pub(crate) fn thread_count(threads: usize) -> usize {
+    match threads {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        threads => threads,
+    }
+}
+
+/// Same as [`try_decrypt`], but spreads the segments across `threads` threads.
+///
+/// The segments are split into runs that are contiguous in the destination, so every thread
+/// owns a disjoint part of `dst`. `threads` of 0 uses the available parallelism, bundles
+/// below `threshold` bytes stay single-threaded.
+pub fn decrypt_parallel(
+    guid: &[u8],
+    bytes: &[u8],
+    key_frag: &[u8],
+    dst: &mut [u8],
+    threads: usize,
+    threshold: usize,
+) -> Result<(), DecryptError> {
+    let threads = thread_count(threads);
+    let total_size = check_input(guid, bytes, key_frag)?;
+    if threads < 2 || total_size < threshold {
+        return try_decrypt(guid, bytes, key_frag, dst);
+    }
+
+    let dst = dst.get_mut(..total_size).ok_or(DecryptError::DstTooSmall)?;
+    let plan = SegmentPlan::new(guid, total_size)?;
+
+    // Destination order, so runs of segments map onto contiguous parts of dst
+    let segments = plan.destination_order();
+    let segments = &segments[..plan.len()];
+
+    let share = total_size.div_ceil(threads);
+    std::thread::scope(|scope| {
+        let mut rest = &mut *dst;
+        let mut segments = segments;
+        let mut start = 0;
+        while !segments.is_empty() {
+            // Take segments until this thread has its share of the bytes
+            let mut count = 0;
+            let mut size = 0;
+            while count < segments.len() && (count == 0 || size < share) {
+                size += segments[count].len;
+                count += 1;
+            }
+            let (run, tail) = segments.split_at(count);
+            let (out, remaining) = rest.split_at_mut(size);
+            segments = tail;
+            rest = remaining;
+
+            let mut copy = move || {
+                for segment in run {
+                    let out = &mut out[segment.dst - start..][..segment.len];
+                    copy_from(bytes, key_frag, segment.src, out);
+                }
+            };
+            start += size;
+            if segments.is_empty() {
+                copy();
+            } else {
+                scope.spawn(copy);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[test]
+fn test_decrypt_parallel() {
+    use crate::decrypt::encrypted_sample;
+
+    let guid = b"67e08c5c-d918-478e-ad8d-58e884fa53b4";
+    for (size, key_len) in [(6442418, 4096), (2786283, 0), (100_000, 77)] {
+        let (want, bytes, key) = encrypted_sample(guid, size, key_len);
+
+        for threads in [1, 2, 3, 8] {
+            let mut dec = vec![0x42; size];
+            assert_eq!(
+                decrypt_parallel(guid, &bytes, &key, &mut dec, threads, 0),
+                Ok(())
+            );
+            assert!(dec == want);
+        }
+    }
+
+    let mut dec = [0; 10];
+    assert_eq!(
+        decrypt_parallel(guid, &[0; 100], &[], &mut dec, 4, 0),
+        Err(DecryptError::DstTooSmall)
+    );
+}
@@ -0,0 +1,108 @@
+use crate::decrypt::{copy_from, DecryptError, SegmentPlan};
+
+/// Re-encrypt a bundle for `new_guid` without decrypting it into a buffer of its own first.
+///
+/// `bytes` and `key_frag` are encrypted for `old_guid`. The result is written to `new_bytes`
+/// followed by `new_key_frag`, which have to add up to the size of the bundle. Every segment of
+/// the new plan is filled from the parts of the old segments covering its destination, so the
+/// output matches decrypting and then encrypting again.
+pub fn rekey(
+    old_guid: &[u8],
+    new_guid: &[u8],
+    bytes: &[u8],
+    key_frag: &[u8],
+    new_bytes: &mut [u8],
+    new_key_frag: &mut [u8],
+) -> Result<(), DecryptError> {
+    if old_guid.is_empty() || new_guid.is_empty() || bytes.is_empty() || new_bytes.is_empty() {
+        return Err(DecryptError::ZeroLength);
+    }
+    let total_size = bytes
+        .len()
+        .checked_add(key_frag.len())
+        .ok_or(DecryptError::LengthOverflow)?;
+    if new_bytes.len().checked_add(new_key_frag.len()) != Some(total_size) {
+        return Err(DecryptError::SizeMismatch);
+    }
+
+    let old_plan = SegmentPlan::new(old_guid, total_size)?;
+    let new_plan = SegmentPlan::new(new_guid, total_size)?;
+
+    let body_len = new_bytes.len();
+    for segment in new_plan.iter() {
+        for old in old_plan.window(segment.dst..segment.dst + segment.len) {
+            // Where the part goes in the new encrypted data
+            let dst = segment.src + (old.dst - segment.dst);
+            let split = body_len.saturating_sub(dst).min(old.len);
+            let head = &mut new_bytes[dst.min(body_len)..][..split];
+            copy_from(bytes, key_frag, old.src, head);
+            let key_dst = dst.saturating_sub(body_len);
+            let tail = &mut new_key_frag[key_dst..][..old.len - split];
+            copy_from(bytes, key_frag, old.src + split, tail);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_rekey() {
+    use crate::decrypt::{encrypted_sample, try_decrypt};
+
+    let old_guid = b"3e7d9b21-6c4a-4f08-b1e5-92a0d8c7f346";
+    let new_guid = b"d05a8f3c-1b72-4e96-8d4f-7a2c61e0b958";
+    for size in [5000, 690036, 2498515] {
+        let (plain, bytes, key) = encrypted_sample(old_guid, size, 100);
+
+        for new_key_len in [0, 1, 100, 4321, size / 3] {
+            let (_, want_bytes, want_key) = encrypted_sample(new_guid, size, new_key_len);
+
+            let mut new_bytes = vec![0; size - new_key_len];
+            let mut new_key = vec![0; new_key_len];
+            assert_eq!(
+                rekey(
+                    old_guid,
+                    new_guid,
+                    &bytes,
+                    &key,
+                    &mut new_bytes,
+                    &mut new_key
+                ),
+                Ok(())
+            );
+            assert!(new_bytes == want_bytes);
+            assert!(new_key == want_key);
+
+            let mut dst = vec![0; size];
+            assert_eq!(
+                try_decrypt(new_guid, &new_bytes, &new_key, &mut dst),
+                Ok(())
+            );
+            assert!(dst == plain);
+        }
+
+        // Same GUID, only the key fragment moves
+        let mut new_bytes = vec![0; size - 50];
+        let mut new_key = vec![0; 50];
+        rekey(
+            old_guid,
+            old_guid,
+            &bytes,
+            &key,
+            &mut new_bytes,
+            &mut new_key,
+        )
+        .unwrap();
+        assert!([new_bytes, new_key].concat() == [bytes, key].concat());
+    }
+
+    let mut out = vec![0; 100];
+    assert_eq!(
+        rekey(old_guid, new_guid, &[0; 60], &[0; 60], &mut out, &mut []),
+        Err(DecryptError::SizeMismatch)
+    );
+    assert_eq!(
+        rekey(old_guid, b"", &[0; 60], &[0; 40], &mut out, &mut []),
+        Err(DecryptError::ZeroLength)
+    );
+}
@@ -0,0 +1,20 @@
+//! Test fixture, also included by `tests/dlopen.rs`, which can't link the crate.
+
+/// UnityFS header followed by pseudo random bytes, `size` bytes in total.
+pub fn sample_bundle(size: usize) -> Vec<u8> {
+    let mut bundle = b"UnityFS\0".to_vec();
+    bundle.extend(7u32.to_be_bytes());
+    bundle.extend(b"5.x.x\0");
+    bundle.extend(b"2021.3.23f1\0");
+    bundle.extend((size as u64).to_be_bytes());
+    bundle.extend([0; 12]);
+    let mut state = 0x2545f4914f6cdd1du64;
+    while bundle.len() < size {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        bundle.push(state as u8);
+    }
+    bundle.truncate(size);
+    bundle
+}
@@ -0,0 +1,103 @@
+use crate::decrypt::{check_input, copy_from, DecryptError, SegmentPlan};
+use crate::unityfs;
+
+/// How the client cuts a bundle into segments and scrambles them.
+///
+/// The discriminants are the scheme ids used over the C ABI. A new client format becomes a new
+/// variant, the old ones stay around for bundles that are still cached.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scheme {
+    /// The format CVR has used since bundles are encrypted
+    V1 = 1,
+}
+
+/// Constants a scheme feeds into [`crate::decrypt::CVRRand`] and [`SegmentPlan`].
+pub(crate) struct Params {
+    /// Initial PRNG state
+    pub seed: i64,
+    /// Segments are between `size / divisor` and twice that long
+    pub divisor: usize,
+    /// Lower bound for `size / divisor`
+    pub min_segment: u32,
+    /// Segments at the start that keep their place, the first one holds the UnityFS header
+    pub fixed: usize,
+}
+
+impl Scheme {
+    /// Every known scheme, newest first, in the order [`probe`] tries them.
+    pub const ALL: &'static [Scheme] = &[Scheme::V1];
+
+    pub fn from_id(id: u32) -> Result<Self, DecryptError> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|&scheme| scheme as u32 == id)
+            .ok_or(DecryptError::UnknownScheme)
+    }
+
+    pub(crate) const fn params(self) -> Params {
+        match self {
+            Self::V1 => Params {
+                seed: 0x3fffffefffffff,
+                divisor: 100,
+                min_segment: 1000,
+                fixed: 1,
+            },
+        }
+    }
+}
+
+/// First scheme in [`Scheme::ALL`] under which the input starts with a valid UnityFS header.
+///
+/// Only the start of the bundle is reassembled and checked for a header with the right size,
+/// so this is cheap compared to decrypting. That start lies in the first segment, which keeps
+/// its place under every scheme that fixes any segments, so those schemes can't be told apart
+/// this way and the newest of them is returned for all their bundles. It only distinguishes
+/// schemes that move the header, `identify` looks past the fixed segments for the GUID. If
+/// none fits, the error of the last scheme tried is returned.
+pub fn probe(guid: &[u8], bytes: &[u8], key_frag: &[u8]) -> Result<Scheme, DecryptError> {
+    let total_size = check_input(guid, bytes, key_frag)?;
+
+    let head_len = unityfs::HEADER_PROBE_LEN.min(total_size);
+    let mut head = [0; unityfs::HEADER_PROBE_LEN];
+    let head = &mut head[..head_len];
+    let mut result = Err(DecryptError::BadSignature);
+    for &scheme in Scheme::ALL {
+        let plan = match SegmentPlan::with_scheme(scheme, guid, total_size) {
+            Ok(plan) => plan,
+            Err(err) => {
+                result = Err(err);
+                continue;
+            }
+        };
+        for segment in plan.window(0..head_len) {
+            let out = &mut head[segment.dst..][..segment.len];
+            copy_from(bytes, key_frag, segment.src, out);
+        }
+        result = unityfs::validate_prefix(head, total_size as u64).map(|_| scheme);
+        if result.is_ok() {
+            break;
+        }
+    }
+    result
+}
+
+#[test]
+fn test_probe() {
+    use crate::decrypt::encrypted_sample;
+
+    assert_eq!(Scheme::from_id(1), Ok(Scheme::V1));
+    assert_eq!(Scheme::from_id(0), Err(DecryptError::UnknownScheme));
+
+    let guid = b"32ceb35d-24fa-469f-8aa4-23851ac68f84";
+    let (_, mut bytes, key) = encrypted_sample(guid, 731337, 500);
+
+    assert_eq!(probe(guid, &bytes, &key), Ok(Scheme::V1));
+    assert_eq!(
+        probe(guid, &bytes, &key[..499]),
+        Err(DecryptError::SizeMismatch)
+    );
+    bytes[0] ^= 0xff;
+    assert_eq!(probe(guid, &bytes, &key), Err(DecryptError::BadSignature));
+}
@@ -0,0 +1,468 @@
+use std::collections::BTreeMap;
+
+use crate::decompress::{decompress_data, parse_bundle};
+use crate::unityfs::{Error, NODE_SERIALIZED_FILE};
+
+/// SerializedFile versions written by Unity 5.0 up to 2022.
+pub const SUPPORTED_VERSIONS: core::ops::RangeInclusive<u32> = 14..=22;
+
+pub const CLASS_GAME_OBJECT: i32 = 1;
+pub const CLASS_TRANSFORM: i32 = 4;
+pub const CLASS_MATERIAL: i32 = 21;
+pub const CLASS_MESH_RENDERER: i32 = 23;
+pub const CLASS_TEXTURE_2D: i32 = 28;
+pub const CLASS_MESH: i32 = 43;
+pub const CLASS_SHADER: i32 = 48;
+pub const CLASS_ANIMATION_CLIP: i32 = 74;
+pub const CLASS_AUDIO_CLIP: i32 = 83;
+pub const CLASS_CUBEMAP: i32 = 89;
+pub const CLASS_ANIMATOR_CONTROLLER: i32 = 91;
+pub const CLASS_ANIMATOR: i32 = 95;
+pub const CLASS_MONO_BEHAVIOUR: i32 = 114;
+pub const CLASS_MONO_SCRIPT: i32 = 115;
+pub const CLASS_SKINNED_MESH_RENDERER: i32 = 137;
+
+/// Name of the classes avatars usually consist of.
+pub fn class_name(class_id: i32) -> Option<&'static str> {
+    Some(match class_id {
+        CLASS_GAME_OBJECT => "GameObject",
+        CLASS_TRANSFORM => "Transform",
+        CLASS_MATERIAL => "Material",
+        CLASS_MESH_RENDERER => "MeshRenderer",
+        CLASS_TEXTURE_2D => "Texture2D",
+        CLASS_MESH => "Mesh",
+        CLASS_SHADER => "Shader",
+        CLASS_ANIMATION_CLIP => "AnimationClip",
+        CLASS_AUDIO_CLIP => "AudioClip",
+        CLASS_CUBEMAP => "Cubemap",
+        CLASS_ANIMATOR_CONTROLLER => "AnimatorController",
+        CLASS_ANIMATOR => "Animator",
+        CLASS_MONO_BEHAVIOUR => "MonoBehaviour",
+        CLASS_MONO_SCRIPT => "MonoScript",
+        CLASS_SKINNED_MESH_RENDERER => "SkinnedMeshRenderer",
+        _ => return None,
+    })
+}
+
+/// An entry of the object table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Object {
+    pub path_id: i64,
+    /// Relative to [`SerializedFile::data_offset`]
+    pub byte_start: u64,
+    pub byte_size: u32,
+    pub class_id: i32,
+}
+
+/// Header, type table and object table of a SerializedFile.
+///
+/// Type trees are skipped, only the class IDs of the types are kept.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SerializedFile<'a> {
+    pub version: u32,
+    pub unity_version: &'a [u8],
+    pub target_platform: i32,
+    pub big_endian: bool,
+    pub data_offset: u64,
+    pub type_tree: bool,
+    /// Class ID of every entry in the type table
+    pub types: Vec<i32>,
+    pub objects: Vec<Object>,
+}
+
+impl<'a> SerializedFile<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        // The header is always big endian
+        let mut reader = Cursor {
+            data,
+            offset: 0,
+            big_endian: true,
+        };
+        reader.u32()?;
+        let mut file_size = reader.u32()? as u64;
+        let version = reader.u32()?;
+        let mut data_offset = reader.u32()? as u64;
+        if !SUPPORTED_VERSIONS.contains(&version) {
+            return Err(Error::UnsupportedSerializedVersion(version));
+        }
+        let big_endian = reader.u8()? != 0;
+        reader.bytes(3)?;
+        if version >= 22 {
+            reader.u32()?;
+            file_size = reader.u64()?;
+            data_offset = reader.u64()?;
+            reader.u64()?;
+        }
+        if data_offset > file_size || file_size > data.len() as u64 {
+            return Err(Error::Truncated);
+        }
+
+        reader.big_endian = big_endian;
+        let unity_version = reader.cstr()?;
+        let target_platform = reader.i32()?;
+        let type_tree = reader.u8()? != 0;
+
+        let mut types = Vec::new();
+        for _ in 0..reader.count()? {
+            let class_id = reader.i32()?;
+            if version >= 16 {
+                // Stripped
+                reader.u8()?;
+            }
+            if version >= 17 {
+                // Script type index
+                reader.bytes(2)?;
+            }
+            let script = match version {
+                ..=15 => class_id < 0,
+                _ => class_id == CLASS_MONO_BEHAVIOUR,
+            };
+            if script {
+                reader.bytes(16)?;
+            }
+            // Type hash
+            reader.bytes(16)?;
+            if type_tree {
+                let node_count = reader.count()?;
+                let string_size = reader.count()?;
+                let node_size = if version >= 19 { 32 } else { 24 };
+                reader.bytes(node_count.checked_mul(node_size).ok_or(Error::Corrupt)?)?;
+                reader.bytes(string_size)?;
+                if version >= 21 {
+                    let dependencies = reader.count()?;
+                    reader.bytes(dependencies.checked_mul(4).ok_or(Error::Corrupt)?)?;
+                }
+            }
+            types.push(class_id);
+        }
+
+        let mut objects = Vec::new();
+        for _ in 0..reader.count()? {
+            reader.offset = reader.offset.next_multiple_of(4);
+            let path_id = reader.i64()?;
+            let byte_start = match version {
+                22.. => reader.u64()?,
+                _ => reader.u32()? as u64,
+            };
+            let byte_size = reader.u32()?;
+            let type_id = reader.i32()?;
+            let class_id = match version {
+                16.. => *usize::try_from(type_id)
+                    .ok()
+                    .and_then(|index| types.get(index))
+                    .ok_or(Error::Corrupt)?,
+                _ => reader.u16()? as i32,
+            };
+            if version < 17 {
+                // Script type index
+                reader.bytes(2)?;
+            }
+            if version == 15 || version == 16 {
+                // Stripped
+                reader.u8()?;
+            }
+
+            let end = byte_start
+                .checked_add(data_offset + byte_size as u64)
+                .ok_or(Error::Corrupt)?;
+            if end > file_size {
+                return Err(Error::Truncated);
+            }
+            objects.push(Object {
+                path_id,
+                byte_start,
+                byte_size,
+                class_id,
+            });
+        }
+
+        Ok(Self {
+            version,
+            unity_version,
+            target_platform,
+            big_endian,
+            data_offset,
+            type_tree,
+            types,
+            objects,
+        })
+    }
+}
+
+/// Cursor over a SerializedFile, the metadata follows the endianness from the header.
+struct Cursor<'a> {
+    data: &'a [u8],
+    offset: usize,
+    big_endian: bool,
+}
+
+impl<'a> Cursor<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let end = self.offset.checked_add(len).ok_or(Error::Truncated)?;
+        let bytes = self.data.get(self.offset..end).ok_or(Error::Truncated)?;
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut bytes: [u8; N] = self.bytes(N)?.try_into().unwrap();
+        if !self.big_endian {
+            bytes.reverse();
+        }
+        Ok(bytes)
+    }
+
+    fn cstr(&mut self) -> Result<&'a [u8], Error> {
+        let rest = self.data.get(self.offset..).ok_or(Error::Truncated)?;
+        let len = rest.iter().position(|&c| c == 0).ok_or(Error::Truncated)?;
+        self.offset += len + 1;
+        Ok(&rest[..len])
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    fn i32(&mut self) -> Result<i32, Error> {
+        Ok(i32::from_be_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+
+    fn i64(&mut self) -> Result<i64, Error> {
+        Ok(i64::from_be_bytes(self.array()?))
+    }
+
+    /// Element count of a table, negative counts are corrupt.
+    fn count(&mut self) -> Result<usize, Error> {
+        usize::try_from(self.i32()?).map_err(|_| Error::Corrupt)
+    }
+}
+
+/// Number and serialized size of the objects of one class.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClassStats {
+    pub count: usize,
+    pub bytes: u64,
+}
+
+/// Object statistics of a bundle.
+///
+/// Textures and audio clips usually keep their payload in a resource node, their object size
+/// only covers the metadata. That data is counted in `resource_bytes`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Keyed by class ID, over all serialized files
+    pub classes: BTreeMap<i32, ClassStats>,
+    /// Size of the nodes that aren't serialized files
+    pub resource_bytes: u64,
+}
+
+impl Report {
+    pub fn add(&mut self, file: &SerializedFile) {
+        for object in &file.objects {
+            let stats = self.classes.entry(object.class_id).or_default();
+            stats.count += 1;
+            stats.bytes += object.byte_size as u64;
+        }
+    }
+
+    pub fn get(&self, class_id: i32) -> ClassStats {
+        self.classes.get(&class_id).copied().unwrap_or_default()
+    }
+}
+
+/// Collect the statistics of a decrypted bundle.
+pub fn bundle_report(file: &[u8]) -> Result<Report, Error> {
+    let mut scratch = Vec::new();
+    let bundle = parse_bundle(file, &mut scratch)?;
+    let data = decompress_data(&bundle, file)?;
+
+    let mut report = Report::default();
+    for node in bundle.nodes() {
+        let contents = usize::try_from(node.offset)
+            .ok()
+            .and_then(|offset| data.get(offset..))
+            .and_then(|rest| rest.get(..usize::try_from(node.size).ok()?))
+            .ok_or(Error::Truncated)?;
+        match node.flags & NODE_SERIALIZED_FILE {
+            0 => report.resource_bytes += node.size,
+            _ => report.add(&SerializedFile::parse(contents)?),
+        }
+    }
+    Ok(report)
+}
+
+/// Little endian SerializedFile with a type tree and the given `(class_id, byte_size)` objects.
+#[cfg(test)]
+pub(crate) fn sample_serialized_file(version: u32, objects: &[(i32, u32)]) -> Vec<u8> {
+    let mut types: Vec<i32> = objects.iter().map(|&(class_id, _)| class_id).collect();
+    types.sort_unstable();
+    types.dedup();
+
+    let mut meta = b"2021.3.23f1\0".to_vec();
+    meta.extend(19i32.to_le_bytes());
+    meta.push(1);
+    meta.extend((types.len() as i32).to_le_bytes());
+    for &class_id in &types {
+        meta.extend(class_id.to_le_bytes());
+        meta.push(0);
+        meta.extend((-1i16).to_le_bytes());
+        if class_id == CLASS_MONO_BEHAVIOUR {
+            meta.extend([0x11; 16]);
+        }
+        meta.extend([0x22; 16]);
+        // One node named "Base"
+        meta.extend(1i32.to_le_bytes());
+        meta.extend(5i32.to_le_bytes());
+        meta.extend(vec![0x33; if version >= 19 { 32 } else { 24 }]);
+        meta.extend(b"Base\0");
+        if version >= 21 {
+            meta.extend(0i32.to_le_bytes());
+        }
+    }
+
+    let header_len = if version >= 22 { 48 } else { 20 };
+    meta.extend((objects.len() as i32).to_le_bytes());
+    let mut start = 0u64;
+    for (path_id, &(class_id, size)) in objects.iter().enumerate() {
+        meta.resize(
+            (header_len + meta.len()).next_multiple_of(4) - header_len,
+            0,
+        );
+        meta.extend((path_id as i64 + 1).to_le_bytes());
+        match version {
+            22.. => meta.extend(start.to_le_bytes()),
+            _ => meta.extend((start as u32).to_le_bytes()),
+        }
+        meta.extend(size.to_le_bytes());
+        let index = types.binary_search(&class_id).unwrap();
+        meta.extend((index as i32).to_le_bytes());
+        start += size.next_multiple_of(8) as u64;
+    }
+
+    let data_offset = (header_len + meta.len()).next_multiple_of(16);
+    let file_size = data_offset + start as usize;
+    let mut file = Vec::new();
+    match version {
+        22.. => {
+            file.extend([0; 8]);
+            file.extend(version.to_be_bytes());
+            file.extend([0; 8]);
+            file.extend((meta.len() as u32).to_be_bytes());
+            file.extend((file_size as u64).to_be_bytes());
+            file.extend((data_offset as u64).to_be_bytes());
+            file.extend([0; 8]);
+        }
+        _ => {
+            file.extend((meta.len() as u32).to_be_bytes());
+            file.extend((file_size as u32).to_be_bytes());
+            file.extend(version.to_be_bytes());
+            file.extend((data_offset as u32).to_be_bytes());
+            file.extend([0; 4]);
+        }
+    }
+    file.extend(meta);
+    file.resize(file_size, 0x44);
+    file
+}
+
+#[test]
+fn test_serialized_file() {
+    let objects = [
+        (CLASS_GAME_OBJECT, 40),
+        (CLASS_MATERIAL, 300),
+        (CLASS_MONO_BEHAVIOUR, 120),
+        (CLASS_MATERIAL, 280),
+        (CLASS_TEXTURE_2D, 250),
+    ];
+    for version in [17, 19, 21, 22] {
+        let data = sample_serialized_file(version, &objects);
+        let file = SerializedFile::parse(&data).unwrap();
+        assert_eq!(file.version, version);
+        assert_eq!(file.unity_version, b"2021.3.23f1");
+        assert_eq!(file.target_platform, 19);
+        assert!(!file.big_endian && file.type_tree);
+        assert_eq!(file.types.len(), 4);
+        assert_eq!(file.objects.len(), 5);
+        assert_eq!(
+            file.objects[3],
+            Object {
+                path_id: 4,
+                byte_start: 40 + 304 + 120,
+                byte_size: 280,
+                class_id: CLASS_MATERIAL,
+            }
+        );
+
+        assert!(SerializedFile::parse(&data[..data.len() - 1]).is_err());
+    }
+
+    let mut data = sample_serialized_file(21, &objects);
+    data[11] = 9;
+    assert_eq!(
+        SerializedFile::parse(&data),
+        Err(Error::UnsupportedSerializedVersion(9))
+    );
+}
+
+#[test]
+fn test_bundle_report() {
+    use crate::unityfs::{sample_bundle_file, BlockInfo, Node, COMPRESSION_LZ4};
+
+    let cab = sample_serialized_file(
+        22,
+        &[
+            (CLASS_MESH, 5000),
+            (CLASS_SKINNED_MESH_RENDERER, 400),
+            (CLASS_TEXTURE_2D, 200),
+            (CLASS_MESH, 3000),
+        ],
+    );
+    let mut data = cab.clone();
+    data.extend([0x55; 70000]);
+    let nodes = [
+        Node {
+            offset: 0,
+            size: cab.len() as u64,
+            flags: NODE_SERIALIZED_FILE,
+            path: b"CAB-a",
+        },
+        Node {
+            offset: cab.len() as u64,
+            size: 70000,
+            flags: 0,
+            path: b"CAB-a.resS",
+        },
+    ];
+    let stored = crate::lz4::compress_literals(&data);
+    let blocks = [BlockInfo {
+        uncompressed_size: data.len() as u32,
+        compressed_size: stored.len() as u32,
+        flags: COMPRESSION_LZ4 as u16,
+    }];
+    let file = sample_bundle_file(&blocks, &nodes, &stored, COMPRESSION_LZ4, false);
+
+    let report = bundle_report(&file).unwrap();
+    assert_eq!(report.classes.len(), 3);
+    assert_eq!(
+        report.get(CLASS_MESH),
+        ClassStats {
+            count: 2,
+            bytes: 8000
+        }
+    );
+    assert_eq!(report.get(CLASS_SKINNED_MESH_RENDERER).count, 1);
+    assert_eq!(report.get(CLASS_AUDIO_CLIP), ClassStats::default());
+    assert_eq!(report.resource_bytes, 70000);
+}
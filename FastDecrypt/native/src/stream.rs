@@ -0,0 +1,119 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::decrypt::{DecryptError, Segment, SegmentPlan};
+
+/// Largest chunk read from the input at once.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Decrypt `total_size` bytes (data followed by key fragment) from `reader` into `writer`.
+///
+/// The input is consumed strictly in order, so `reader` may be a download that is still in
+/// progress. Only a single chunk of the bundle is held in memory at a time.
+pub fn decrypt_stream<R: Read, W: Write + Seek>(
+    guid: &[u8],
+    total_size: usize,
+    reader: R,
+    mut writer: W,
+) -> io::Result<()> {
+    let plan =
+        plan(guid, total_size).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+    let mut position = None;
+    copy_segments(plan.segments(), reader, |offset, buf| {
+        if position != Some(offset) {
+            writer.seek(SeekFrom::Start(offset))?;
+        }
+        writer.write_all(buf)?;
+        position = Some(offset + buf.len() as u64);
+        Ok(())
+    })?;
+    writer.flush()
+}
+
+/// Validate the input and compute the segments, see [`crate::decrypt::try_decrypt`].
+pub(crate) fn plan(guid: &[u8], total_size: usize) -> Result<SegmentPlan, DecryptError> {
+    if guid.is_empty() || total_size == 0 {
+        return Err(DecryptError::ZeroLength);
+    }
+    SegmentPlan::new(guid, total_size)
+}
+
+/// Read the segments in the order they are stored and pass each decrypted chunk to `write`
+/// together with its offset in the bundle.
+pub(crate) fn copy_segments<R: Read>(
+    segments: &[Segment],
+    mut reader: R,
+    mut write: impl FnMut(u64, &[u8]) -> io::Result<()>,
+) -> io::Result<()> {
+    // Segments are in storage order, the last one doesn't end the bundle
+    let largest = segments.iter().map(|s| s.end - s.offset).max().unwrap_or(0);
+    let mut chunk = vec![0; CHUNK_SIZE.min(largest)];
+    for segment in segments.iter() {
+        let mut offset = segment.offset;
+        while offset < segment.end {
+            let len = (segment.end - offset).min(chunk.len());
+            let buf = &mut chunk[..len];
+            reader.read_exact(buf)?;
+            write(offset as u64, buf)?;
+            offset += len;
+        }
+    }
+
+    Ok(())
+}
+
+/// [`Read`] adapter over the C read callback.
+pub(crate) struct CallbackReader {
+    pub read: crate::ReadCallback,
+    pub ctx: *mut core::ffi::c_void,
+}
+
+impl Read for CallbackReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match unsafe { (self.read)(self.ctx, buf.as_mut_ptr(), buf.len()) } {
+            len if len < 0 => Err(io::ErrorKind::Other.into()),
+            len => Ok(len as usize),
+        }
+    }
+}
+
+#[cfg(test)]
+struct Trickle<'a>(&'a [u8]);
+
+#[cfg(test)]
+impl Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Hand out odd sized pieces like a network stream would
+        let len = buf.len().min(self.0.len()).min(1337);
+        buf[..len].copy_from_slice(&self.0[..len]);
+        self.0 = &self.0[len..];
+        Ok(len)
+    }
+}
+
+#[test]
+fn test_decrypt_stream() {
+    use crate::decrypt::encrypted_sample;
+
+    let guid = b"6586c486-4731-4fae-a2d2-de415cd8bcd6";
+    let (dec, bytes, key) = encrypted_sample(guid, 2759924, 4096);
+    let enc = [bytes, key].concat();
+
+    let mut out = io::Cursor::new(Vec::new());
+    decrypt_stream(guid, enc.len(), Trickle(&enc), &mut out).unwrap();
+    assert!(out.into_inner() == dec);
+
+    // Input ends early
+    let mut out = io::Cursor::new(Vec::new());
+    let err = decrypt_stream(guid, enc.len(), Trickle(&enc[..1000]), &mut out).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+    let err = decrypt_stream(b"", enc.len(), Trickle(&enc), &mut out).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    let inner = err
+        .into_inner()
+        .unwrap()
+        .downcast::<DecryptError>()
+        .unwrap();
+    assert_eq!(*inner, DecryptError::ZeroLength);
+}
@@ -0,0 +1,421 @@
+use crate::decrypt::DecryptError;
+
+/// Format versions written by the Unity releases CVR has shipped with.
+pub const SUPPORTED_VERSIONS: core::ops::RangeInclusive<u32> = 6..=8;
+
+/// Bytes at the start of a bundle that are reassembled to look at the header before the rest,
+/// plenty for a UnityFS header.
+pub const HEADER_PROBE_LEN: usize = 256;
+
+/// The fixed part at the start of every UnityFS bundle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header<'a> {
+    pub version: u32,
+    pub unity_version: &'a [u8],
+    pub unity_revision: &'a [u8],
+    pub size: u64,
+    pub compressed_blocks_info_size: u32,
+    pub uncompressed_blocks_info_size: u32,
+    pub flags: u32,
+}
+
+impl<'a> Header<'a> {
+    /// Parse the header, returns it together with the number of bytes it occupies.
+    pub fn parse(data: &'a [u8]) -> Option<(Self, usize)> {
+        let mut reader = Reader { data, offset: 0 };
+        if reader.cstr()? != b"UnityFS" {
+            return None;
+        }
+        let header = Self {
+            version: reader.u32()?,
+            unity_version: reader.cstr()?,
+            unity_revision: reader.cstr()?,
+            size: reader.u64()?,
+            compressed_blocks_info_size: reader.u32()?,
+            uncompressed_blocks_info_size: reader.u32()?,
+            flags: reader.u32()?,
+        };
+        Some((header, reader.offset))
+    }
+}
+
+/// Sanity check a decrypted bundle.
+///
+/// Checks the signature, the format version and that the header's size matches. The header
+/// sits in the first segment, which is never moved, so this doesn't detect a wrong GUID unless
+/// it changes that segment's length. Truncated downloads or key fragments reliably fail the
+/// size check.
+pub fn validate(bundle: &[u8]) -> Result<Header<'_>, DecryptError> {
+    validate_prefix(bundle, bundle.len() as u64)
+}
+
+/// [`validate`] when only the start of a bundle of `size` bytes is at hand.
+pub fn validate_prefix(prefix: &[u8], size: u64) -> Result<Header<'_>, DecryptError> {
+    let (header, _) = Header::parse(prefix).ok_or(DecryptError::BadSignature)?;
+    if !SUPPORTED_VERSIONS.contains(&header.version) {
+        return Err(DecryptError::UnsupportedVersion);
+    }
+    if header.size != size {
+        return Err(DecryptError::SizeMismatch);
+    }
+    Ok(header)
+}
+
+/// Lower bits of [`Header::flags`] and [`BlockInfo::flags`] holding the compression type.
+pub const COMPRESSION_MASK: u32 = 0x3f;
+/// The blocks info is stored at the end of the file instead of after the header.
+pub const BLOCKS_INFO_AT_END: u32 = 0x80;
+/// The block data starts on a 16 byte boundary after the blocks info.
+pub const BLOCK_INFO_PADDING: u32 = 0x200;
+
+pub const COMPRESSION_NONE: u32 = 0;
+pub const COMPRESSION_LZMA: u32 = 1;
+pub const COMPRESSION_LZ4: u32 = 2;
+pub const COMPRESSION_LZ4HC: u32 = 3;
+
+/// Why a bundle couldn't be read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Not a UnityFS bundle
+    BadSignature,
+    /// A table or block reaches past the end of the data
+    Truncated,
+    /// The compression type isn't supported, carries the type
+    UnsupportedCompression(u32),
+    /// A block has flags besides compression and streaming set, carries the flags
+    UnsupportedBlockFlags(u16),
+    /// The SerializedFile format version isn't supported, carries the version
+    UnsupportedSerializedVersion(u32),
+    /// Decompression failed or produced the wrong amount of data
+    Corrupt,
+}
+
+/// [`Node::flags`] bit marking a SerializedFile, the other nodes hold raw resource data.
+pub const NODE_SERIALIZED_FILE: u32 = 4;
+
+/// Size and compression of a data block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockInfo {
+    pub uncompressed_size: u32,
+    pub compressed_size: u32,
+    pub flags: u16,
+}
+
+/// A file in the bundle, `offset` is relative to the start of the uncompressed block data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Node<'a> {
+    pub offset: u64,
+    pub size: u64,
+    pub flags: u32,
+    pub path: &'a [u8],
+}
+
+/// A parsed bundle with the blocks info and directory decompressed.
+pub struct Bundle<'a> {
+    pub header: Header<'a>,
+    /// Offset of the first data block in the bundle
+    pub data_offset: usize,
+    blocks_info: &'a [u8],
+    block_count: usize,
+    node_count: usize,
+}
+
+impl<'a> Bundle<'a> {
+    /// Parse header, blocks info and directory.
+    ///
+    /// A compressed blocks info is decompressed into `scratch`, which has to hold at least
+    /// [`Header::uncompressed_blocks_info_size`] bytes. Only LZ4 is supported here, see
+    /// [`Bundle::parse_with`].
+    pub fn parse(bundle: &'a [u8], scratch: &'a mut [u8]) -> Result<Self, Error> {
+        Self::parse_with(bundle, scratch, |compression, src, dst| match compression {
+            COMPRESSION_LZ4 | COMPRESSION_LZ4HC => match crate::lz4::decompress(src, dst) {
+                Some(len) if len == dst.len() => Ok(()),
+                _ => Err(Error::Corrupt),
+            },
+            other => Err(Error::UnsupportedCompression(other)),
+        })
+    }
+
+    /// Same as [`Bundle::parse`], but hands compressed blocks info to `decompress` together with
+    /// its compression type.
+    pub fn parse_with(
+        bundle: &'a [u8],
+        scratch: &'a mut [u8],
+        decompress: impl FnOnce(u32, &[u8], &mut [u8]) -> Result<(), Error>,
+    ) -> Result<Self, Error> {
+        let (header, mut offset) = Header::parse(bundle).ok_or(Error::BadSignature)?;
+        if header.version >= 7 {
+            offset = offset.next_multiple_of(16);
+        }
+
+        let compressed_size = header.compressed_blocks_info_size as usize;
+        let info_offset = match header.flags & BLOCKS_INFO_AT_END {
+            0 => offset,
+            _ => bundle
+                .len()
+                .checked_sub(compressed_size)
+                .ok_or(Error::Truncated)?,
+        };
+        let compressed = bundle
+            .get(info_offset..info_offset + compressed_size)
+            .ok_or(Error::Truncated)?;
+        if header.flags & BLOCKS_INFO_AT_END == 0 {
+            offset += compressed_size;
+        }
+        if header.flags & BLOCK_INFO_PADDING != 0 {
+            offset = offset.next_multiple_of(16);
+        }
+
+        let uncompressed_size = header.uncompressed_blocks_info_size as usize;
+        let blocks_info: &'a [u8] = match header.flags & COMPRESSION_MASK {
+            COMPRESSION_NONE => compressed,
+            compression => {
+                let scratch = scratch
+                    .get_mut(..uncompressed_size)
+                    .ok_or(Error::Truncated)?;
+                decompress(compression, compressed, scratch)?;
+                scratch
+            }
+        };
+
+        // Walk both tables once, so the iterators can't run into malformed entries
+        let mut reader = Reader {
+            data: blocks_info,
+            offset: 16,
+        };
+        let block_count = reader.u32().ok_or(Error::Truncated)? as usize;
+        for _ in 0..block_count {
+            reader.bytes(10).ok_or(Error::Truncated)?;
+        }
+        let node_count = reader.u32().ok_or(Error::Truncated)? as usize;
+        for _ in 0..node_count {
+            reader.bytes(20).ok_or(Error::Truncated)?;
+            reader.cstr().ok_or(Error::Truncated)?;
+        }
+
+        Ok(Self {
+            header,
+            data_offset: offset,
+            blocks_info,
+            block_count,
+            node_count,
+        })
+    }
+
+    /// Hash Unity stores in front of the blocks info.
+    pub fn uncompressed_data_hash(&self) -> &'a [u8] {
+        &self.blocks_info[..16]
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = BlockInfo> + 'a {
+        let mut reader = Reader {
+            data: self.blocks_info,
+            offset: 20,
+        };
+        (0..self.block_count).map_while(move |_| {
+            Some(BlockInfo {
+                uncompressed_size: reader.u32()?,
+                compressed_size: reader.u32()?,
+                flags: reader.u16()?,
+            })
+        })
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = Node<'a>> + 'a {
+        let mut reader = Reader {
+            data: self.blocks_info,
+            offset: 24 + self.block_count * 10,
+        };
+        (0..self.node_count).map_while(move |_| {
+            Some(Node {
+                offset: reader.u64()?,
+                size: reader.u64()?,
+                flags: reader.u32()?,
+                path: reader.cstr()?,
+            })
+        })
+    }
+}
+
+/// Big endian cursor over the bundle.
+pub(crate) struct Reader<'a> {
+    pub data: &'a [u8],
+    pub offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.offset..self.offset.checked_add(len)?)?;
+        self.offset += len;
+        Some(bytes)
+    }
+
+    pub fn cstr(&mut self) -> Option<&'a [u8]> {
+        let rest = self.data.get(self.offset..)?;
+        let len = rest.iter().position(|&c| c == 0)?;
+        self.offset += len + 1;
+        Some(&rest[..len])
+    }
+
+    pub fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    pub fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+}
+
+/// Minimal bundle header without blocks info, `size` bytes long.
+#[cfg(test)]
+pub(crate) fn sample_header(size: usize) -> Vec<u8> {
+    sample_header_with(size, 0, 0, 0x43)
+}
+
+#[cfg(test)]
+fn sample_header_with(size: usize, compressed: usize, uncompressed: usize, flags: u32) -> Vec<u8> {
+    let mut data = b"UnityFS\0".to_vec();
+    data.extend(7u32.to_be_bytes());
+    data.extend(b"5.x.x\0");
+    data.extend(b"2021.3.23f1\0");
+    data.extend((size as u64).to_be_bytes());
+    data.extend((compressed as u32).to_be_bytes());
+    data.extend((uncompressed as u32).to_be_bytes());
+    data.extend(flags.to_be_bytes());
+    data
+}
+
+/// Uncompressed blocks info and directory for the given blocks and nodes.
+#[cfg(test)]
+pub(crate) fn sample_blocks_info(blocks: &[BlockInfo], nodes: &[Node]) -> Vec<u8> {
+    let mut info = vec![0xaa; 16];
+    info.extend((blocks.len() as u32).to_be_bytes());
+    for block in blocks {
+        info.extend(block.uncompressed_size.to_be_bytes());
+        info.extend(block.compressed_size.to_be_bytes());
+        info.extend(block.flags.to_be_bytes());
+    }
+    info.extend((nodes.len() as u32).to_be_bytes());
+    for node in nodes {
+        info.extend(node.offset.to_be_bytes());
+        info.extend(node.size.to_be_bytes());
+        info.extend(node.flags.to_be_bytes());
+        info.extend(node.path);
+        info.push(0);
+    }
+    info
+}
+
+/// Assemble a bundle around already compressed blocks.
+///
+/// `compression` applies to the blocks info, which is LZ4 encoded as literals if requested.
+#[cfg(test)]
+pub(crate) fn sample_bundle_file(
+    blocks: &[BlockInfo],
+    nodes: &[Node],
+    data: &[u8],
+    compression: u32,
+    at_end: bool,
+) -> Vec<u8> {
+    let info = sample_blocks_info(blocks, nodes);
+    let stored = match compression {
+        COMPRESSION_NONE => info.clone(),
+        _ => crate::lz4::compress_literals(&info),
+    };
+    let flags = compression | BLOCK_INFO_PADDING | if at_end { BLOCKS_INFO_AT_END } else { 0 };
+
+    let header_len = sample_header_with(0, 0, 0, 0).len().next_multiple_of(16);
+    let size = match at_end {
+        true => header_len + data.len() + stored.len(),
+        false => (header_len + stored.len()).next_multiple_of(16) + data.len(),
+    };
+    let mut bundle = sample_header_with(size, stored.len(), info.len(), flags);
+    bundle.resize(header_len, 0);
+    if !at_end {
+        bundle.extend(&stored);
+        bundle.resize(bundle.len().next_multiple_of(16), 0);
+    }
+    bundle.extend(data);
+    if at_end {
+        bundle.extend(&stored);
+    }
+    bundle
+}
+
+#[test]
+fn test_validate() {
+    let mut bundle = sample_header(4096);
+    bundle.resize(4096, 0);
+    let header = validate(&bundle).unwrap();
+    assert_eq!(header.version, 7);
+    assert_eq!(header.unity_revision, b"2021.3.23f1");
+    assert_eq!(header.flags, 0x43);
+
+    assert_eq!(validate(&bundle[..4000]), Err(DecryptError::SizeMismatch));
+    assert_eq!(validate(&bundle[..20]), Err(DecryptError::BadSignature));
+
+    bundle[11] = 9;
+    assert_eq!(validate(&bundle), Err(DecryptError::UnsupportedVersion));
+    bundle[0] = b'u';
+    assert_eq!(validate(&bundle), Err(DecryptError::BadSignature));
+}
+
+#[test]
+fn test_bundle() {
+    let blocks = [
+        BlockInfo {
+            uncompressed_size: 100,
+            compressed_size: 100,
+            flags: 0x40,
+        },
+        BlockInfo {
+            uncompressed_size: 20,
+            compressed_size: 20,
+            flags: 0,
+        },
+    ];
+    let nodes = [
+        Node {
+            offset: 0,
+            size: 90,
+            flags: 4,
+            path: b"CAB-0123456789abcdef",
+        },
+        Node {
+            offset: 90,
+            size: 30,
+            flags: 0,
+            path: b"CAB-0123456789abcdef.resS",
+        },
+    ];
+    let data: Vec<u8> = (0..120).collect();
+
+    for compression in [COMPRESSION_NONE, COMPRESSION_LZ4HC] {
+        for at_end in [false, true] {
+            let file = sample_bundle_file(&blocks, &nodes, &data, compression, at_end);
+            assert!(validate(&file).is_ok());
+
+            let mut scratch = vec![0; 1024];
+            let bundle = Bundle::parse(&file, &mut scratch).unwrap();
+            assert_eq!(bundle.data_offset % 16, 0);
+            assert_eq!(&file[bundle.data_offset..][..120], &data[..]);
+            assert_eq!(bundle.uncompressed_data_hash(), &[0xaa; 16]);
+            assert!(bundle.blocks().eq(blocks));
+            assert!(bundle.nodes().eq(nodes));
+        }
+    }
+
+    let file = sample_bundle_file(&blocks, &nodes, &data, COMPRESSION_LZ4, false);
+    assert!(Bundle::parse(&file, &mut [0; 16]).is_err());
+    assert!(Bundle::parse(&file[..80], &mut [0; 1024]).is_err());
+
+    let file = sample_bundle_file(&blocks, &nodes, &data, 5, false);
+    assert_eq!(
+        Bundle::parse(&file, &mut [0; 1024]).err(),
+        Some(Error::UnsupportedCompression(5))
+    );
+}
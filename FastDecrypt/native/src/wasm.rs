@@ -0,0 +1,14 @@
+//! JavaScript bindings, build with `--target wasm32-unknown-unknown --features wasm` and run
+//! the output through `wasm-bindgen`.
+
+use wasm_bindgen::prelude::*;
+
+use crate::api;
+
+/// `decrypt(guid: string, data: Uint8Array, key: Uint8Array): Uint8Array`
+///
+/// Throws with the [`api::Error`] message if the input can't be decrypted.
+#[wasm_bindgen]
+pub fn decrypt(guid: &str, data: &[u8], key: &[u8]) -> Result<Vec<u8>, JsError> {
+    api::decrypt(guid, data, key).map_err(JsError::from)
+}
@@ -0,0 +1,368 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::thread::JoinHandle;
+
+use crate::decrypt::{try_decrypt, DecryptError};
+
+/// Jobs that may wait for a free worker, together with results waiting to be picked up, before
+/// [`Worker::submit`] refuses new ones.
+pub const QUEUE_CAPACITY: usize = 64;
+
+pub type JobId = u64;
+
+/// Called on the worker thread once a job is finished, or on the thread calling
+/// [`Worker::cancel`] if it is cancelled.
+pub type Completion = Box<dyn FnOnce(JobId, Result<(), DecryptError>) + Send>;
+
+/// Buffers of a job. They are owned by the submitter and have to stay valid until the job
+/// is finished.
+#[derive(Clone, Copy)]
+pub struct JobBuffers {
+    pub data: *const u8,
+    pub data_len: usize,
+    pub key: *const u8,
+    pub key_len: usize,
+    pub dst: *mut u8,
+    pub dst_len: usize,
+}
+
+unsafe impl Send for JobBuffers {}
+
+struct Job {
+    guid: Vec<u8>,
+    buffers: JobBuffers,
+    completion: Option<Completion>,
+}
+
+enum State {
+    Queued(Job),
+    Running,
+    Done(Result<(), DecryptError>),
+}
+
+#[derive(Default)]
+struct Jobs {
+    queue: VecDeque<JobId>,
+    states: HashMap<JobId, State>,
+    next_id: JobId,
+    shutdown: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    jobs: Mutex<Jobs>,
+    queued: Condvar,
+    finished: Condvar,
+}
+
+/// Decrypts submitted jobs on background threads.
+///
+/// Results of jobs without a completion callback are kept until they are picked up by
+/// [`Worker::poll`] or [`Worker::wait`], or dropped by [`Worker::cancel`], and count towards
+/// the capacity until then. Jobs with a callback are forgotten once it ran.
+pub struct Worker {
+    shared: Arc<Shared>,
+    threads: Vec<JoinHandle<()>>,
+    capacity: usize,
+}
+
+impl Worker {
+    pub fn new(threads: usize, capacity: usize) -> Self {
+        let shared = Arc::new(Shared::default());
+        let threads = (0..threads.max(1))
+            .map(|_| {
+                let shared = shared.clone();
+                std::thread::spawn(move || run(&shared))
+            })
+            .collect();
+        Self {
+            shared,
+            threads,
+            capacity,
+        }
+    }
+
+    /// Worker used by the C exports, started on first use.
+    pub fn global() -> &'static Self {
+        static WORKER: OnceLock<Worker> = OnceLock::new();
+        WORKER.get_or_init(|| Self::new(1, QUEUE_CAPACITY))
+    }
+
+    /// Queue a job, fails with [`DecryptError::QueueFull`] if too many jobs or results are
+    /// waiting.
+    ///
+    /// # Safety
+    ///
+    /// The buffers have to stay valid and must not be touched until the job is finished.
+    pub unsafe fn submit(
+        &self,
+        guid: &[u8],
+        buffers: JobBuffers,
+        completion: Option<Completion>,
+    ) -> Result<JobId, DecryptError> {
+        let mut jobs = self.shared.jobs.lock().unwrap();
+        let waiting = jobs
+            .states
+            .values()
+            .filter(|state| !matches!(state, State::Running))
+            .count();
+        if waiting >= self.capacity {
+            return Err(DecryptError::QueueFull);
+        }
+        jobs.next_id += 1;
+        let id = jobs.next_id;
+        let job = Job {
+            guid: guid.to_vec(),
+            buffers,
+            completion,
+        };
+        jobs.states.insert(id, State::Queued(job));
+        jobs.queue.push_back(id);
+        self.shared.queued.notify_one();
+        Ok(id)
+    }
+
+    /// Result of a finished job, [`DecryptError::Pending`] while it is queued or running.
+    pub fn poll(&self, id: JobId) -> Result<(), DecryptError> {
+        let mut jobs = self.shared.jobs.lock().unwrap();
+        match jobs.states.get(&id) {
+            None => Err(DecryptError::UnknownJob),
+            Some(State::Queued(_) | State::Running) => Err(DecryptError::Pending),
+            Some(State::Done(_)) => match jobs.states.remove(&id) {
+                Some(State::Done(result)) => result,
+                _ => unreachable!(),
+            },
+        }
+    }
+
+    /// Block until the job is finished and return its result.
+    pub fn wait(&self, id: JobId) -> Result<(), DecryptError> {
+        let mut jobs = self.shared.jobs.lock().unwrap();
+        loop {
+            match jobs.states.get(&id) {
+                None => return Err(DecryptError::UnknownJob),
+                Some(State::Queued(_) | State::Running) => {
+                    jobs = self.shared.finished.wait(jobs).unwrap();
+                }
+                Some(State::Done(_)) => match jobs.states.remove(&id) {
+                    Some(State::Done(result)) => return result,
+                    _ => unreachable!(),
+                },
+            }
+        }
+    }
+
+    /// Drop a job that hasn't started yet, its completion callback runs right away on this
+    /// thread with [`DecryptError::Cancelled`]. Running jobs can't be interrupted and report
+    /// [`DecryptError::Pending`], finished jobs are forgotten along with their result.
+    pub fn cancel(&self, id: JobId) -> Result<(), DecryptError> {
+        let mut jobs = self.shared.jobs.lock().unwrap();
+        match jobs.states.get(&id) {
+            None => return Err(DecryptError::UnknownJob),
+            Some(State::Running) => return Err(DecryptError::Pending),
+            Some(State::Done(_)) => {
+                jobs.states.remove(&id);
+                return Ok(());
+            }
+            Some(State::Queued(_)) => {}
+        }
+        jobs.queue.retain(|&queued| queued != id);
+        let Some(State::Queued(job)) = jobs.states.remove(&id) else {
+            unreachable!()
+        };
+        match job.completion {
+            Some(completion) => {
+                drop(jobs);
+                completion(id, Err(DecryptError::Cancelled));
+            }
+            None => {
+                jobs.states
+                    .insert(id, State::Done(Err(DecryptError::Cancelled)));
+                self.shared.finished.notify_all();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.shared.jobs.lock().unwrap().shutdown = true;
+        self.shared.queued.notify_all();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(shared: &Shared) {
+    loop {
+        let mut jobs = shared.jobs.lock().unwrap();
+        let (id, job) = loop {
+            if jobs.shutdown {
+                return;
+            }
+            if let Some(id) = jobs.queue.pop_front() {
+                match jobs.states.insert(id, State::Running) {
+                    Some(State::Queued(job)) => break (id, job),
+                    _ => unreachable!(),
+                }
+            }
+            jobs = shared.queued.wait(jobs).unwrap();
+        };
+        drop(jobs);
+
+        let result = unsafe {
+            let buffers = job.buffers;
+            try_decrypt(
+                &job.guid,
+                raw_slice(buffers.data, buffers.data_len),
+                raw_slice(buffers.key, buffers.key_len),
+                match buffers.dst.is_null() {
+                    true => &mut [],
+                    false => core::slice::from_raw_parts_mut(buffers.dst, buffers.dst_len),
+                },
+            )
+        };
+
+        let mut jobs = shared.jobs.lock().unwrap();
+        match job.completion {
+            Some(completion) => {
+                jobs.states.remove(&id);
+                drop(jobs);
+                completion(id, result);
+            }
+            None => {
+                jobs.states.insert(id, State::Done(result));
+                shared.finished.notify_all();
+            }
+        }
+    }
+}
+
+/// Null is only valid for empty slices, those are rejected by [`try_decrypt`] where it matters.
+unsafe fn raw_slice<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    match ptr.is_null() {
+        true => &[],
+        false => core::slice::from_raw_parts(ptr, len),
+    }
+}
+
+#[test]
+fn test_worker() {
+    use crate::decrypt::encrypted_sample;
+    use std::sync::mpsc;
+
+    let guid = b"17c267db-18c4-4900-bb73-ad323f082640";
+    let (want, bytes, key) = encrypted_sample(guid, 1227329, 300);
+
+    let buffers = |dst: &mut Vec<u8>| JobBuffers {
+        data: bytes.as_ptr(),
+        data_len: bytes.len(),
+        key: key.as_ptr(),
+        key_len: key.len(),
+        dst: dst.as_mut_ptr(),
+        dst_len: dst.len(),
+    };
+
+    // Declared before the worker so they outlive its threads
+    let mut dsts = vec![vec![0; want.len()]; 3];
+    let mut short = vec![0; 10];
+    let worker = Worker::new(2, 2);
+    let ids: Vec<_> = dsts
+        .iter_mut()
+        .take(2)
+        .map(|dst| unsafe { worker.submit(guid, buffers(dst), None).unwrap() })
+        .collect();
+    for &id in &ids {
+        assert_eq!(worker.wait(id), Ok(()));
+        assert_eq!(worker.poll(id), Err(DecryptError::UnknownJob));
+    }
+    assert!(dsts[0] == want && dsts[1] == want);
+
+    let (tx, rx) = mpsc::channel();
+    let completion: Completion = Box::new(move |id, result| tx.send((id, result)).unwrap());
+    let id = unsafe {
+        worker
+            .submit(guid, buffers(&mut dsts[2]), Some(completion))
+            .unwrap()
+    };
+    assert_eq!(rx.recv().unwrap(), (id, Ok(())));
+    assert!(dsts[2] == want);
+
+    // Results stay around until they are picked up
+    let id = unsafe { worker.submit(guid, buffers(&mut short), None).unwrap() };
+    let result = loop {
+        match worker.poll(id) {
+            Err(DecryptError::Pending) => std::thread::yield_now(),
+            result => break result,
+        }
+    };
+    assert_eq!(result, Err(DecryptError::DstTooSmall));
+    assert_eq!(worker.poll(id), Err(DecryptError::UnknownJob));
+    let id = unsafe { worker.submit(guid, buffers(&mut short), None).unwrap() };
+    assert_eq!(worker.wait(id), Err(DecryptError::DstTooSmall));
+}
+
+#[test]
+fn test_worker_queue() {
+    use crate::decrypt::encrypted_sample;
+    use std::sync::mpsc;
+
+    let guid = b"17c267db-18c4-4900-bb73-ad323f082640";
+    let (plain, bytes, _) = encrypted_sample(guid, 5000, 0);
+
+    let buffers = |dst: &mut Vec<u8>| JobBuffers {
+        data: bytes.as_ptr(),
+        data_len: bytes.len(),
+        key: core::ptr::null(),
+        key_len: 0,
+        dst: dst.as_mut_ptr(),
+        dst_len: dst.len(),
+    };
+
+    // Declared before the worker so they outlive its threads
+    let mut dsts = vec![vec![0; plain.len()]; 6];
+    let worker = Worker::new(1, 2);
+
+    // Block the only thread in a completion callback until `release` is sent or dropped
+    let (started, started_rx) = mpsc::channel();
+    let (release, release_rx) = mpsc::channel::<()>();
+    let blocker: Completion = Box::new(move |_, _| {
+        started.send(()).unwrap();
+        let _ = release_rx.recv();
+    });
+    unsafe { worker.submit(guid, buffers(&mut dsts[0]), Some(blocker)) }.unwrap();
+    started_rx.recv().unwrap();
+
+    let first = unsafe { worker.submit(guid, buffers(&mut dsts[1]), None) }.unwrap();
+    let second = unsafe { worker.submit(guid, buffers(&mut dsts[2]), None) }.unwrap();
+    assert_eq!(
+        unsafe { worker.submit(guid, buffers(&mut dsts[3]), None) },
+        Err(DecryptError::QueueFull)
+    );
+
+    // The result of a cancelled job still takes up room until it is picked up
+    assert_eq!(worker.cancel(second), Ok(()));
+    assert_eq!(
+        unsafe { worker.submit(guid, buffers(&mut dsts[3]), None) },
+        Err(DecryptError::QueueFull)
+    );
+    assert_eq!(worker.wait(second), Err(DecryptError::Cancelled));
+    let third = unsafe { worker.submit(guid, buffers(&mut dsts[3]), None) }.unwrap();
+
+    release.send(()).unwrap();
+    assert_eq!(worker.wait(first), Ok(()));
+    assert_eq!(worker.wait(third), Ok(()));
+    assert!(dsts[1] == plain && dsts[2] != plain && dsts[3] == plain);
+
+    // Cancelling a finished job drops its result
+    let (tx, rx) = mpsc::channel();
+    let done = unsafe { worker.submit(guid, buffers(&mut dsts[4]), None) }.unwrap();
+    let completion: Completion = Box::new(move |_, result| tx.send(result).unwrap());
+    unsafe { worker.submit(guid, buffers(&mut dsts[5]), Some(completion)) }.unwrap();
+    assert_eq!(rx.recv().unwrap(), Ok(()));
+    assert_eq!(worker.cancel(done), Ok(()));
+    assert_eq!(worker.poll(done), Err(DecryptError::UnknownJob));
+    assert_eq!(worker.cancel(done), Err(DecryptError::UnknownJob));
+}
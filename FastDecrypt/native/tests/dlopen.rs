@@ -0,0 +1,215 @@
+//! Loads the built library and goes through its C ABI, the same way the mod does.
+//!
+//! The library is built with the release profile and default features, like the one that gets
+//! shipped. Set `LIBDEC_PATH` to check another build instead.
+#![cfg(not(target_family = "wasm"))]
+
+use std::ffi::c_void;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use libloading::{Library, Symbol};
+
+#[path = "../src/sample.rs"]
+mod sample;
+use sample::sample_bundle;
+
+type Decrypt = unsafe extern "C" fn(*const u8, usize, *const u8, usize, *const u8, usize, *mut u8);
+type TryDecrypt = unsafe extern "C" fn(
+    *const u8,
+    usize,
+    *const u8,
+    usize,
+    *const u8,
+    usize,
+    *mut u8,
+    usize,
+) -> u32;
+type Progress = unsafe extern "C" fn(*mut c_void, usize, usize) -> u32;
+type TryDecryptProgress = unsafe extern "C" fn(
+    *const u8,
+    usize,
+    *const u8,
+    usize,
+    *const u8,
+    usize,
+    *mut u8,
+    usize,
+    usize,
+    Progress,
+    *mut c_void,
+) -> u32;
+type Encrypt = unsafe extern "C" fn(*const u8, usize, *const u8, usize, usize, *mut u8, *mut u8);
+type TryEncrypt =
+    unsafe extern "C" fn(*const u8, usize, *const u8, usize, usize, *mut u8, *mut u8) -> u32;
+
+fn library_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("LIBDEC_PATH") {
+        return path.into();
+    }
+
+    // Build it the way it's shipped. What cargo builds for the tests uses the test profile,
+    // which unwinds and can't be loaded without std.
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let target_dir = manifest_dir.join("target").join("dlopen");
+    let status = Command::new(env!("CARGO"))
+        .args([
+            "rustc",
+            "--release",
+            "--lib",
+            "--crate-type",
+            "cdylib",
+            "--manifest-path",
+        ])
+        .arg(manifest_dir.join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .status()
+        .unwrap();
+    assert!(status.success(), "building the library failed");
+
+    let name = format!(
+        "{}libdec{}",
+        std::env::consts::DLL_PREFIX,
+        std::env::consts::DLL_SUFFIX
+    );
+    target_dir.join("release").join(name)
+}
+
+/// Counts the reports in `ctx` and stops after the fourth.
+unsafe extern "C" fn stop_after_four(ctx: *mut c_void, _done: usize, _total: usize) -> u32 {
+    let calls = &mut *ctx.cast::<u32>();
+    *calls += 1;
+    (*calls == 4) as u32
+}
+
+#[test]
+fn test_exports() {
+    let path = library_path();
+    let library = unsafe { Library::new(&path) }
+        .unwrap_or_else(|err| panic!("can't load {}: {err}", path.display()));
+
+    unsafe {
+        let decrypt: Symbol<Decrypt> = library.get(b"decrypt\0").unwrap();
+        let try_decrypt: Symbol<TryDecrypt> = library.get(b"try_decrypt\0").unwrap();
+        let try_decrypt_validated: Symbol<TryDecrypt> =
+            library.get(b"try_decrypt_validated\0").unwrap();
+        let try_decrypt_progress: Symbol<TryDecryptProgress> =
+            library.get(b"try_decrypt_progress\0").unwrap();
+        let encrypt: Symbol<Encrypt> = library.get(b"encrypt\0").unwrap();
+        let try_encrypt: Symbol<TryEncrypt> = library.get(b"try_encrypt\0").unwrap();
+
+        let guid = b"9d1d8585-9c0b-40d9-8721-76f21cc745f2";
+        let plain = sample_bundle(1_048_576);
+        let key_len = 4096;
+        let mut data = vec![0; plain.len() - key_len];
+        let mut key = vec![0; key_len];
+        encrypt(
+            guid.as_ptr(),
+            guid.len(),
+            plain.as_ptr(),
+            plain.len(),
+            key_len,
+            data.as_mut_ptr(),
+            key.as_mut_ptr(),
+        );
+        assert!(data[..] != plain[..data.len()]);
+
+        // SizeMismatch for a key fragment longer than the bundle, nothing is written
+        let status = try_encrypt(
+            guid.as_ptr(),
+            guid.len(),
+            plain.as_ptr(),
+            plain.len(),
+            plain.len() + 1,
+            data.as_mut_ptr(),
+            key.as_mut_ptr(),
+        );
+        assert_eq!(status, 13);
+
+        let mut dst = vec![0; plain.len()];
+        decrypt(
+            guid.as_ptr(),
+            guid.len(),
+            data.as_ptr(),
+            data.len(),
+            key.as_ptr(),
+            key.len(),
+            dst.as_mut_ptr(),
+        );
+        assert!(dst == plain);
+
+        for function in [&try_decrypt, &try_decrypt_validated] {
+            let mut dst = vec![0; plain.len()];
+            let status = function(
+                guid.as_ptr(),
+                guid.len(),
+                data.as_ptr(),
+                data.len(),
+                key.as_ptr(),
+                key.len(),
+                dst.as_mut_ptr(),
+                dst.len(),
+            );
+            assert_eq!(status, 0);
+            assert!(dst == plain);
+        }
+
+        // NullPointer and DstTooSmall
+        let status = try_decrypt(
+            std::ptr::null(),
+            0,
+            data.as_ptr(),
+            data.len(),
+            key.as_ptr(),
+            key.len(),
+            dst.as_mut_ptr(),
+            dst.len(),
+        );
+        assert_eq!(status, 1);
+        let status = try_decrypt(
+            guid.as_ptr(),
+            guid.len(),
+            data.as_ptr(),
+            data.len(),
+            key.as_ptr(),
+            key.len(),
+            dst.as_mut_ptr(),
+            dst.len() - 1,
+        );
+        assert_eq!(status, 3);
+
+        // A missing key fragment fails the size check of the header
+        let status = try_decrypt_validated(
+            guid.as_ptr(),
+            guid.len(),
+            data.as_ptr(),
+            data.len(),
+            std::ptr::null(),
+            0,
+            dst.as_mut_ptr(),
+            dst.len(),
+        );
+        assert_eq!(status, 13);
+
+        // Aborted by the callback, the first 4 intervals are done
+        let mut dst = vec![0; plain.len()];
+        let mut calls = 0u32;
+        let status = try_decrypt_progress(
+            guid.as_ptr(),
+            guid.len(),
+            data.as_ptr(),
+            data.len(),
+            key.as_ptr(),
+            key.len(),
+            dst.as_mut_ptr(),
+            dst.len(),
+            65536,
+            stop_after_four,
+            (&mut calls as *mut u32).cast(),
+        );
+        assert_eq!((status, calls), (20, 4));
+        assert!(dst[..262144] == plain[..262144]);
+        assert!(dst[262144..].iter().all(|&b| b == 0));
+    }
+}